@@ -0,0 +1,69 @@
+//! Generates the `TxProcessor` grpc service of `proto/txprocessor.proto` with the `grpc`
+//! feature, from the messages derived in `src/grpc.rs` and `src/protobuf_input.rs` as
+//! the rest of the protobuf types are, so building it doesn't need `protoc`.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    fn method(name: &str, route_name: &str, input_type: &str, output_type: &str) -> Method {
+        Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(input_type)
+            .output_type(output_type)
+            .codec_path("tonic::codec::ProstCodec")
+            .build()
+    }
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let service = Service::builder()
+            .name("TxProcessor")
+            .package("txprocessor")
+            .method(method(
+                "submit_tx",
+                "SubmitTx",
+                "crate::TxMessage",
+                "crate::grpc::TxResult",
+            ))
+            .method(
+                Method::builder()
+                    .name("submit_tx_stream")
+                    .route_name("SubmitTxStream")
+                    .input_type("crate::TxMessage")
+                    .output_type("crate::grpc::TxResult")
+                    .codec_path("tonic::codec::ProstCodec")
+                    .client_streaming()
+                    .server_streaming()
+                    .build(),
+            )
+            .method(method(
+                "get_account",
+                "GetAccount",
+                "crate::grpc::GetAccountRequest",
+                "crate::grpc::AccountMessage",
+            ))
+            .method(method(
+                "list_accounts",
+                "ListAccounts",
+                "crate::grpc::ListAccountsRequest",
+                "crate::grpc::ListAccountsResponse",
+            ))
+            .method(method(
+                "get_tx",
+                "GetTx",
+                "crate::grpc::GetTxRequest",
+                "crate::grpc::StoredTxMessage",
+            ))
+            .build();
+        // the generated `connect` relies on the 2021 prelude, the client is built on a
+        // `Channel` instead
+        Builder::new().build_transport(false).compile(&[service]);
+    }
+}
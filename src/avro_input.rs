@@ -0,0 +1,58 @@
+//! The avro reader, for transactions published with an avro schema. The records
+//! are read against `TX_SCHEMA`, a writer schema it can't read fails before any
+//! record is, so a drifted schema is caught up front.
+
+use crate::{check_headers, json_tx, ReadOptions, Tx, TxError};
+use anyhow::{anyhow, Context, Result};
+use avro::{schema_compatibility::SchemaCompatibility, Reader, Schema};
+use std::{convert::TryFrom, io::Read, sync::OnceLock};
+
+/// The schema the records are read with, the csv columns with a nullable amount,
+/// transfer destination and timestamp
+const TX_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Tx",
+    "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", "string"], "default": null},
+        {"name": "to", "type": ["null", "int"], "default": null},
+        {"name": "timestamp", "type": ["null", "long"], "default": null}
+    ]
+}"#;
+
+fn tx_schema() -> &'static Schema {
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::parse_str(TX_SCHEMA).expect("TX_SCHEMA is a valid schema"))
+}
+
+/// Reads the transactions of an avro object container file, a block at a time as
+/// the stream is pulled. A record that doesn't deserialize into a transaction
+/// only fails once it's reached.
+pub fn read_avro_txs<R: Read>(
+    rdr: R,
+    options: &ReadOptions,
+) -> Result<impl Iterator<Item = Result<Tx, TxError>>> {
+    let schema = tx_schema();
+    let reader = Reader::with_schema(schema, rdr).context("failed reading avro header")?;
+    SchemaCompatibility::can_read(reader.writer_schema(), schema).map_err(|e| {
+        anyhow!(
+            "the avro schema doesn't match the transaction schema: {}",
+            e
+        )
+    })?;
+    if let Schema::Record(record) = reader.writer_schema() {
+        check_headers(record.fields.iter().map(|f| f.name.as_str()), options)?;
+    }
+
+    // records go through the json representation to share its amount handling
+    let precision = options.precision;
+    Ok(reader
+        .map(move |value| {
+            let value = value.context("failed reading avro record")?;
+            let element = serde_json::Value::try_from(value)?;
+            json_tx(element, precision)
+        })
+        .map(|tx| tx.map_err(TxError::parse)))
+}
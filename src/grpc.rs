@@ -0,0 +1,327 @@
+//! The `TxProcessor` grpc service of `proto/txprocessor.proto`, served by `serve-grpc`
+//! for the services which don't speak the http api. The calls are answered on a tokio
+//! runtime of their own while the engine stays on the calling thread, taking them one
+//! at a time over a channel, the stores taking a single writer. Rust services call it
+//! with the generated [`TxProcessorClient`](tx_processor_client::TxProcessorClient).
+
+use crate::{
+    protobuf_input::message_tx, Account, ClientId, Engine, ReadOptions, Store, TxError, TxId,
+    TxMessage,
+};
+use anyhow::{Context, Result};
+use prost::Message;
+use std::{
+    convert::TryFrom,
+    marker::PhantomData,
+    net::{SocketAddr, TcpListener},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+use tokio::sync::{mpsc as async_mpsc, oneshot};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+include!(concat!(env!("OUT_DIR"), "/txprocessor.TxProcessor.rs"));
+
+use tx_processor_server::{TxProcessor, TxProcessorServer};
+
+/// How often the engine checks whether it should stop while no call comes in
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The `TxResult` message, what was done with a transaction
+#[derive(Clone, PartialEq, Message)]
+pub struct TxResult {
+    #[prost(uint32, tag = "1")]
+    pub tx: u32,
+    #[prost(string, optional, tag = "2")]
+    pub outcome: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub error: Option<String>,
+}
+
+/// The `GetAccountRequest` message
+#[derive(Clone, PartialEq, Message)]
+pub struct GetAccountRequest {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+}
+
+/// The `Account` message, the columns of the report
+#[derive(Clone, PartialEq, Message)]
+pub struct AccountMessage {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(string, tag = "2")]
+    pub available: String,
+    #[prost(string, tag = "3")]
+    pub held: String,
+    #[prost(string, tag = "4")]
+    pub total: String,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+    #[prost(bool, tag = "6")]
+    pub frozen: bool,
+}
+
+impl From<Account> for AccountMessage {
+    fn from(account: Account) -> Self {
+        AccountMessage {
+            client: u32::from(account.client_id),
+            available: account.available.to_string(),
+            held: account.held.to_string(),
+            total: account.total.to_string(),
+            locked: account.locked,
+            frozen: account.frozen,
+        }
+    }
+}
+
+/// The `ListAccountsRequest` message
+#[derive(Clone, PartialEq, Message)]
+pub struct ListAccountsRequest {}
+
+/// The `ListAccountsResponse` message
+#[derive(Clone, PartialEq, Message)]
+pub struct ListAccountsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub accounts: Vec<AccountMessage>,
+}
+
+/// The `GetTxRequest` message
+#[derive(Clone, PartialEq, Message)]
+pub struct GetTxRequest {
+    #[prost(uint32, tag = "1")]
+    pub tx: u32,
+}
+
+/// The `StoredTx` message, a stored deposit, withdrawal or transfer
+#[derive(Clone, PartialEq, Message)]
+pub struct StoredTxMessage {
+    #[prost(uint32, tag = "1")]
+    pub tx: u32,
+    #[prost(string, tag = "2")]
+    pub tx_type: String,
+    #[prost(uint32, tag = "3")]
+    pub client: u32,
+    #[prost(string, tag = "4")]
+    pub amount: String,
+    #[prost(string, tag = "5")]
+    pub status: String,
+    #[prost(string, tag = "6")]
+    pub disputed: String,
+    #[prost(int64, optional, tag = "7")]
+    pub timestamp: Option<i64>,
+}
+
+/// A call for the engine, run on its thread
+type Call<S> = Box<dyn FnOnce(&mut Engine<S>) + Send>;
+
+/// Serves the service on `addr` until `should_stop` says otherwise, e.g. on SIGINT.
+/// The calls still waiting for the engine then fail as unavailable.
+pub fn serve_grpc<S: Store + 'static>(
+    engine: &mut Engine<S>,
+    addr: SocketAddr,
+    options: &ReadOptions,
+    should_stop: impl Fn() -> bool,
+) -> Result<()> {
+    // bound here so a taken address fails the command rather than the runtime
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed serving grpc on {}", addr))?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Runtime::new().context("failed starting the grpc runtime")?;
+    let (calls, engine_calls) = mpsc::channel();
+    let service = Service::<S> {
+        calls,
+        options: options.clone(),
+        engine: PhantomData,
+    };
+    let (shutdown, shut_down) = oneshot::channel::<()>();
+    let server = thread::Builder::new()
+        .name("grpc".to_string())
+        .spawn(move || {
+            runtime.block_on(async move {
+                let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener)?);
+                Server::builder()
+                    .add_service(TxProcessorServer::new(service))
+                    .serve_with_incoming_shutdown(incoming, async {
+                        let _ = shut_down.await;
+                    })
+                    .await
+                    .context("the grpc server failed")
+            })
+        })
+        .context("failed spawning the grpc server")?;
+    tracing::info!(%addr, "serving grpc");
+
+    while !should_stop() && !server.is_finished() {
+        match engine_calls.recv_timeout(POLL_INTERVAL) {
+            Ok(call) => call(engine),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    drop(engine_calls);
+    let _ = shutdown.send(());
+    server
+        .join()
+        .unwrap_or_else(|panicked| std::panic::resume_unwind(panicked))
+}
+
+pub(crate) struct Service<S: Store> {
+    calls: mpsc::Sender<Call<S>>,
+    options: ReadOptions,
+    engine: PhantomData<fn() -> S>,
+}
+
+// derived it would need `S: Clone`, the engine isn't shared
+impl<S: Store> Clone for Service<S> {
+    fn clone(&self) -> Self {
+        Service {
+            calls: self.calls.clone(),
+            options: self.options.clone(),
+            engine: PhantomData,
+        }
+    }
+}
+
+impl<S: Store> Service<S> {
+    /// Runs `call` on the engine's thread, once the calls before it are done
+    async fn call<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(&mut Engine<S>) -> T + Send + 'static,
+    ) -> Result<T, Status> {
+        let (reply, replied) = oneshot::channel();
+        self.calls
+            .send(Box::new(move |engine| {
+                let _ = reply.send(call(engine));
+            }))
+            .map_err(|_| Status::unavailable("the engine stopped"))?;
+        replied
+            .await
+            .map_err(|_| Status::unavailable("the engine stopped"))
+    }
+
+    /// Processes a transaction, a malformed one failing as an invalid argument
+    async fn submit(&self, message: TxMessage) -> Result<TxResult, Status> {
+        let tx = message.tx;
+        let tx = message_tx(message, self.options.precision).map_err(|e| {
+            Status::invalid_argument(format!("invalid transaction {}: {:#}", tx, e))
+        })?;
+        let id = tx.id;
+        match self.call(move |engine| engine.submit(tx)).await? {
+            Ok(outcome) => Ok(TxResult {
+                tx: id,
+                outcome: Some(outcome.to_string()),
+                error: None,
+            }),
+            Err(TxError::Storage(e)) => Err(Status::internal(e.to_string())),
+            Err(e) => Ok(TxResult {
+                tx: id,
+                outcome: None,
+                error: Some(format!("{:#}", anyhow::Error::from(e))),
+            }),
+        }
+    }
+}
+
+fn internal(e: anyhow::Error) -> Status {
+    Status::internal(format!("{:#}", e))
+}
+
+#[tonic::async_trait]
+impl<S: Store + 'static> TxProcessor for Service<S> {
+    async fn submit_tx(&self, request: Request<TxMessage>) -> Result<Response<TxResult>, Status> {
+        self.submit(request.into_inner()).await.map(Response::new)
+    }
+
+    type SubmitTxStreamStream = ReceiverStream<Result<TxResult, Status>>;
+
+    /// Processes the transactions in the order they come, a malformed one answered
+    /// with its error without ending the stream
+    async fn submit_tx_stream(
+        &self,
+        request: Request<Streaming<TxMessage>>,
+    ) -> Result<Response<Self::SubmitTxStreamStream>, Status> {
+        let mut messages = request.into_inner();
+        let service = self.clone();
+        let (results, answered) = async_mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let result = match messages.message().await {
+                    Ok(Some(message)) => {
+                        let tx = message.tx;
+                        match service.submit(message).await {
+                            Err(status) if status.code() == tonic::Code::InvalidArgument => {
+                                Ok(TxResult {
+                                    tx,
+                                    outcome: None,
+                                    error: Some(status.message().to_string()),
+                                })
+                            }
+                            result => result,
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(status) => Err(status),
+                };
+                let failed = result.is_err();
+                // the client went away
+                if results.send(result).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(answered)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<AccountMessage>, Status> {
+        let client = request.into_inner().client;
+        let client_id = ClientId::try_from(client)
+            .map_err(|_| Status::invalid_argument(format!("{} is an invalid client", client)))?;
+        match self.call(move |engine| engine.account(client_id)).await? {
+            Ok(Some(account)) => Ok(Response::new(account.into())),
+            Ok(None) => Err(Status::not_found(format!(
+                "no account of client {}",
+                client_id
+            ))),
+            Err(e) => Err(internal(e)),
+        }
+    }
+
+    async fn list_accounts(
+        &self,
+        _: Request<ListAccountsRequest>,
+    ) -> Result<Response<ListAccountsResponse>, Status> {
+        let accounts = self
+            .call(|engine| engine.accounts())
+            .await?
+            .map_err(internal)?;
+        Ok(Response::new(ListAccountsResponse {
+            accounts: accounts.into_iter().map(AccountMessage::from).collect(),
+        }))
+    }
+
+    async fn get_tx(
+        &self,
+        request: Request<GetTxRequest>,
+    ) -> Result<Response<StoredTxMessage>, Status> {
+        let id: TxId = request.into_inner().tx;
+        match self.call(move |engine| engine.transaction(id)).await? {
+            Ok(Some(tx)) => Ok(Response::new(StoredTxMessage {
+                tx: id,
+                tx_type: tx.tx_type.to_string(),
+                client: u32::from(tx.client_id),
+                amount: tx.amount.to_string(),
+                status: tx.status.to_string(),
+                disputed: tx.disputed.to_string(),
+                timestamp: tx.happened(),
+            })),
+            Ok(None) => Err(Status::not_found(format!("no transaction {}", id))),
+            Err(e) => Err(internal(e)),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! The callbacks a library user registers on an [`Engine`](crate::Engine), run as the
+//! transactions are processed, for side effects of their own. They can't change what's
+//! applied, a check which rejects transactions is a [`RiskRule`](crate::RiskRule).
+
+use crate::{Account, Tx, TxOutcome};
+
+type TxHook = Box<dyn FnMut(&Tx) + Send>;
+type RejectionHook = Box<dyn FnMut(&Tx, TxOutcome) + Send>;
+type AccountHook = Box<dyn FnMut(&Account) + Send>;
+type BalanceHook = Box<dyn FnMut(Option<&Account>, &Account) + Send>;
+
+/// The callbacks of an engine, see [`Engine::with_hooks`](crate::Engine::with_hooks)
+#[derive(Default)]
+pub struct Hooks {
+    tx_accepted: Vec<TxHook>,
+    tx_rejected: Vec<RejectionHook>,
+    account_locked: Vec<AccountHook>,
+    balance_changed: Vec<BalanceHook>,
+}
+
+impl Hooks {
+    /// Calls `hook` with every transaction applied
+    pub fn on_tx_accepted(mut self, hook: impl FnMut(&Tx) + Send + 'static) -> Self {
+        self.tx_accepted.push(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` with every transaction which wasn't applied, and why, the skipped ones
+    /// left out
+    pub fn on_tx_rejected(mut self, hook: impl FnMut(&Tx, TxOutcome) + Send + 'static) -> Self {
+        self.tx_rejected.push(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` with every account a transaction locked, e.g. a chargeback
+    pub fn on_account_locked(mut self, hook: impl FnMut(&Account) + Send + 'static) -> Self {
+        self.account_locked.push(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` with every account whose funds a transaction changed, as it was
+    /// before, none for a new one, and as it is after
+    pub fn on_balance_changed(
+        mut self,
+        hook: impl FnMut(Option<&Account>, &Account) + Send + 'static,
+    ) -> Self {
+        self.balance_changed.push(Box::new(hook));
+        self
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tx_accepted.is_empty() && self.tx_rejected.is_empty() && !self.watch_accounts()
+    }
+
+    /// Whether the accounts a transaction touches have to be read before and after it
+    pub(crate) fn watch_accounts(&self) -> bool {
+        !self.account_locked.is_empty() || !self.balance_changed.is_empty()
+    }
+
+    pub(crate) fn processed(&mut self, tx: &Tx, outcome: TxOutcome) {
+        match outcome {
+            TxOutcome::Applied => self.tx_accepted.iter_mut().for_each(|hook| hook(tx)),
+            TxOutcome::Skipped => {}
+            outcome => self
+                .tx_rejected
+                .iter_mut()
+                .for_each(|hook| hook(tx, outcome)),
+        }
+    }
+
+    /// Calls the account hooks with an account touched by a transaction, as it was before
+    /// and is after it
+    pub(crate) fn changed(&mut self, before: Option<&Account>, after: &Account) {
+        if after.locked && !before.is_some_and(|before| before.locked) {
+            self.account_locked.iter_mut().for_each(|hook| hook(after));
+        }
+        let funds = |account: &Account| (account.available, account.held, account.total);
+        if before.map(funds) != Some(funds(after)) {
+            self.balance_changed
+                .iter_mut()
+                .for_each(|hook| hook(before, after));
+        }
+    }
+}
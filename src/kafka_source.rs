@@ -0,0 +1,91 @@
+//! The kafka source of `consume`, applying the transactions of a topic as they're
+//! produced. Each message is read as an input of its own, e.g. an ndjson object or an
+//! avro container, and its offset is only committed once the store committed its
+//! transactions, so a crash or a rebalance redelivers what wasn't stored rather than
+//! losing it. A redelivered deposit or withdrawal is rejected as a `duplicate_tx`.
+
+use crate::{process_message, Engine, InputFormat, ReadOptions, Store};
+use anyhow::{anyhow, Context, Result};
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance},
+    ClientContext, Message,
+};
+use std::time::Duration;
+
+/// How often the consumer checks whether it should stop while no message comes in
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Where `consume` reads the transactions from
+#[derive(Debug, Clone)]
+pub struct KafkaOptions {
+    /// the bootstrap brokers, comma separated
+    pub brokers: String,
+    pub topic: String,
+    /// the consumer group, the partitions of the topic are shared among its members
+    pub group: String,
+}
+
+/// Logs the partitions the consumer is given and loses as the group rebalances
+struct RebalanceLog;
+
+impl ClientContext for RebalanceLog {}
+
+impl ConsumerContext for RebalanceLog {
+    fn post_rebalance(&self, rebalance: &Rebalance<'_>) {
+        match rebalance {
+            Rebalance::Assign(partitions) => {
+                tracing::info!(partitions = partitions.count(), "partitions assigned")
+            }
+            Rebalance::Revoke(partitions) => {
+                tracing::info!(partitions = partitions.count(), "partitions revoked")
+            }
+            Rebalance::Error(e) => tracing::warn!(error = %e, "rebalance failed"),
+        }
+    }
+}
+
+/// Applies the messages of the topic until `should_stop` says otherwise, e.g. on
+/// SIGINT, a message being applied is finished first. Its malformed transactions are
+/// handled by the `on_error` of the engine's config, a message which can't be read at
+/// all being one of them. Failing the run leaves the message uncommitted.
+pub fn consume<S: Store>(
+    engine: &mut Engine<S>,
+    kafka: &KafkaOptions,
+    format: InputFormat,
+    options: &ReadOptions,
+    should_stop: impl Fn() -> bool,
+) -> Result<()> {
+    let consumer: BaseConsumer<RebalanceLog> = ClientConfig::new()
+        .set("bootstrap.servers", &kafka.brokers)
+        .set("group.id", &kafka.group)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create_with_context(RebalanceLog)
+        .context("failed creating the kafka consumer")?;
+    consumer
+        .subscribe(&[&kafka.topic])
+        .with_context(|| format!("failed subscribing to {}", kafka.topic))?;
+    tracing::info!(topic = %kafka.topic, group = %kafka.group, "consuming");
+
+    while !should_stop() {
+        let message = match consumer.poll(POLL_INTERVAL) {
+            Some(message) => message.context("failed consuming")?,
+            None => continue,
+        };
+
+        let payload = message.payload().unwrap_or_default();
+        process_message(engine, payload, format, options).map_err(|e| {
+            anyhow!(
+                "failed applying message {} of partition {}: {:#}",
+                message.offset(),
+                message.partition(),
+                anyhow::Error::from(e)
+            )
+        })?;
+        consumer
+            .commit_message(&message, CommitMode::Sync)
+            .context("failed committing the offset")?;
+    }
+    Ok(())
+}
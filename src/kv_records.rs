@@ -0,0 +1,273 @@
+//! The encoding of the records for the key-value stores, keys are big endian
+//! so they iterate in order, values are comma separated text.
+
+use crate::{
+    AccountRecord, Accrual, Amount, AuditEntry, Notification, Tx, TxOutcome, TxRecord, TxStatus,
+};
+use anyhow::{anyhow, bail, Result};
+use std::str::FromStr;
+
+pub(crate) fn parse<T: FromStr>(field: &str) -> Result<T> {
+    field
+        .parse()
+        .map_err(|_| anyhow!("{} is an invalid field", field))
+}
+
+/// An empty field as none
+fn optional<T: FromStr>(field: &str) -> Result<Option<T>> {
+    match field {
+        "" => Ok(None),
+        field => parse(field).map(Some),
+    }
+}
+
+pub(crate) fn amount_of(field: &str) -> Result<Amount> {
+    parse(field).map(Amount::from_minor_units)
+}
+
+pub(crate) fn encode_account(account: &AccountRecord) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{}",
+        account.available.minor_units(),
+        account.held.minor_units(),
+        account.total.minor_units(),
+        account.status,
+        account.frozen,
+        account.overdraft_limit.minor_units(),
+        account.withdrawn.minor_units(),
+        account.withdrawal_day
+    )
+}
+
+/// Accounts stored before freezes have no frozen field, none of them is, the ones
+/// stored before overdrafts no limit, none of them can be overdrawn, and the ones
+/// stored before daily withdrawal limits no withdrawals
+pub(crate) fn decode_account(value: &[u8]) -> Result<AccountRecord> {
+    let value = std::str::from_utf8(value)?;
+    let fields: Vec<&str> = value.split(',').collect();
+    let (available, held, total, status, added) = match *fields {
+        [available, held, total, status, ref added @ ..] if added.len() <= 4 => {
+            (available, held, total, status, added)
+        }
+        _ => bail!("{} is an invalid record", value),
+    };
+    let field = |i: usize, missing| added.get(i).copied().unwrap_or(missing);
+    Ok(AccountRecord {
+        available: amount_of(available)?,
+        held: amount_of(held)?,
+        total: amount_of(total)?,
+        status: parse(status)?,
+        frozen: parse(field(0, "false"))?,
+        overdraft_limit: amount_of(field(1, "0"))?,
+        withdrawn: amount_of(field(2, "0"))?,
+        withdrawal_day: parse(field(3, "0"))?,
+    })
+}
+
+pub(crate) fn encode_txrecord(txrecord: &TxRecord) -> String {
+    let optional = |value: Option<i64>| value.map(|value| value.to_string()).unwrap_or_default();
+    format!(
+        "{},{},{},{},{},{},{}",
+        txrecord.tx_type,
+        txrecord.client_id,
+        txrecord.amount.minor_units(),
+        txrecord.status,
+        txrecord.disputed.minor_units(),
+        optional(txrecord.ingested_at),
+        optional(txrecord.timestamp)
+    )
+}
+
+/// Transactions stored before partial disputes have no disputed field, the ones
+/// disputed by then are disputed as a whole, nor do ones stored before ingest
+/// times have an ingest time, or before timestamps a timestamp
+pub(crate) fn decode_txrecord(value: &[u8]) -> Result<TxRecord> {
+    let value = std::str::from_utf8(value)?;
+    let fields: Vec<&str> = value.split(',').collect();
+    let (tx_type, client_id, amount, status, disputed, ingested_at, timestamp) = match *fields {
+        [tx_type, client_id, amount, status] => (tx_type, client_id, amount, status, None, "", ""),
+        [tx_type, client_id, amount, status, disputed] => {
+            (tx_type, client_id, amount, status, Some(disputed), "", "")
+        }
+        [tx_type, client_id, amount, status, disputed, ingested_at] => (
+            tx_type,
+            client_id,
+            amount,
+            status,
+            Some(disputed),
+            ingested_at,
+            "",
+        ),
+        [tx_type, client_id, amount, status, disputed, ingested_at, timestamp] => (
+            tx_type,
+            client_id,
+            amount,
+            status,
+            Some(disputed),
+            ingested_at,
+            timestamp,
+        ),
+        _ => bail!("{} is an invalid record", value),
+    };
+    let amount = amount_of(amount)?;
+    let status = parse(status)?;
+    let disputed = match disputed {
+        Some(disputed) => amount_of(disputed)?,
+        None if matches!(status, TxStatus::InDispute | TxStatus::Chargeback) => amount,
+        None => Amount::ZERO,
+    };
+    Ok(TxRecord {
+        tx_type: parse(tx_type)?,
+        client_id: parse(client_id)?,
+        amount,
+        status,
+        disputed,
+        ingested_at: optional(ingested_at)?,
+        timestamp: optional(timestamp)?,
+    })
+}
+
+/// A transaction as logged, an empty amount is a dispute, resolve or chargeback
+/// and an empty destination anything but a transfer
+pub(crate) fn encode_tx(tx: &Tx) -> String {
+    let amount = tx
+        .amount
+        .map(|amount| amount.minor_units().to_string())
+        .unwrap_or_default();
+    let to_client = tx
+        .to_client
+        .map(|to_client| to_client.to_string())
+        .unwrap_or_default();
+    let timestamp = tx
+        .timestamp
+        .map(|timestamp| timestamp.to_string())
+        .unwrap_or_default();
+    format!(
+        "{},{},{},{},{},{}",
+        tx.id, tx.tx_type, tx.client_id, amount, to_client, timestamp
+    )
+}
+
+/// Transactions logged before transfers have no destination field, nor before
+/// timestamps a timestamp
+fn tx_of(fields: &[&str]) -> Result<Tx> {
+    let (id, tx_type, client_id, amount, to_client, timestamp) = match *fields {
+        [id, tx_type, client_id, amount] => (id, tx_type, client_id, amount, "", ""),
+        [id, tx_type, client_id, amount, to_client] => {
+            (id, tx_type, client_id, amount, to_client, "")
+        }
+        [id, tx_type, client_id, amount, to_client, timestamp] => {
+            (id, tx_type, client_id, amount, to_client, timestamp)
+        }
+        _ => bail!("{} is an invalid record", fields.join(",")),
+    };
+    Ok(Tx {
+        id: parse(id)?,
+        tx_type: parse(tx_type)?,
+        client_id: parse(client_id)?,
+        amount: match amount {
+            "" => None,
+            amount => Some(amount_of(amount)?),
+        },
+        to_client: optional(to_client)?,
+        timestamp: optional(timestamp)?,
+    })
+}
+
+pub(crate) fn decode_tx(value: &[u8]) -> Result<Tx> {
+    let value = std::str::from_utf8(value)?;
+    tx_of(&value.split(',').collect::<Vec<_>>())
+}
+
+pub(crate) fn encode_rejected((tx, reason): &(Tx, TxOutcome)) -> String {
+    format!("{},{}", encode_tx(tx), reason)
+}
+
+pub(crate) fn decode_rejected(value: &[u8]) -> Result<(Tx, TxOutcome)> {
+    let value = std::str::from_utf8(value)?;
+    let (tx, reason) = value
+        .rsplit_once(',')
+        .ok_or_else(|| anyhow!("{} is an invalid record", value))?;
+    Ok((decode_tx(tx.as_bytes())?, parse(reason)?))
+}
+
+/// An audit log entry without its seq, which is the key it's stored under
+pub(crate) fn encode_audit(entry: &AuditEntry) -> String {
+    format!(
+        "{},{},{},{},{}",
+        encode_tx(&entry.tx),
+        entry.outcome,
+        entry.available.minor_units(),
+        entry.held.minor_units(),
+        entry.total.minor_units()
+    )
+}
+
+pub(crate) fn decode_audit(seq: u64, value: &[u8]) -> Result<AuditEntry> {
+    let value = std::str::from_utf8(value)?;
+    let fields: Vec<&str> = value.split(',').collect();
+    // entries from before timestamps have one field less
+    let (tx, outcome, available, held, total) = match *fields {
+        [ref tx @ .., outcome, available, held, total] if matches!(tx.len(), 5 | 6) => {
+            (tx, outcome, available, held, total)
+        }
+        _ => bail!("{} is an invalid record", value),
+    };
+    Ok(AuditEntry {
+        seq,
+        tx: tx_of(tx)?,
+        outcome: parse(outcome)?,
+        available: amount_of(available)?,
+        held: amount_of(held)?,
+        total: amount_of(total)?,
+    })
+}
+
+/// A notification without its seq, which is the key it's stored under. The url and
+/// the payload are on lines of their own, the payload having commas.
+pub(crate) fn encode_notification(notification: &Notification) -> String {
+    format!("{}\n{}", notification.url, notification.payload)
+}
+
+pub(crate) fn decode_notification(seq: u64, value: &[u8]) -> Result<Notification> {
+    let value = std::str::from_utf8(value)?;
+    let (url, payload) = value
+        .split_once('\n')
+        .ok_or_else(|| anyhow!("{} is an invalid record", value))?;
+    Ok(Notification {
+        seq,
+        url: url.to_string(),
+        payload: payload.to_string(),
+    })
+}
+
+/// An accrual without its period, which is the key it's stored under
+pub(crate) fn encode_accrual(accrual: &Accrual) -> String {
+    format!(
+        "{},{},{},{},{}",
+        accrual.rate,
+        accrual.tx_id,
+        accrual.accounts,
+        accrual.interest.minor_units(),
+        accrual.completed
+    )
+}
+
+pub(crate) fn decode_accrual(as_of: &[u8], value: &[u8]) -> Result<Accrual> {
+    let value = std::str::from_utf8(value)?;
+    let fields: Vec<&str> = value.split(',').collect();
+    let (rate, tx_id, accounts, interest, completed) = match *fields {
+        [rate, tx_id, accounts, interest, completed] => {
+            (rate, tx_id, accounts, interest, completed)
+        }
+        _ => bail!("{} is an invalid record", value),
+    };
+    Ok(Accrual {
+        as_of: std::str::from_utf8(as_of)?.to_string(),
+        rate: parse(rate)?,
+        tx_id: parse(tx_id)?,
+        accounts: parse(accounts)?,
+        interest: amount_of(interest)?,
+        completed: parse(completed)?,
+    })
+}
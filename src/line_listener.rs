@@ -0,0 +1,317 @@
+//! The line protocol of `listen`, for the legacy integrations writing transactions to
+//! a socket. Each line is a transaction, a json object or a csv record, and is answered
+//! with a line of its own once it's been processed:
+//! - `ack <tx> <outcome>` once it's applied, or skipped
+//! - `err <tx> <outcome>` once it's rejected, e.g. `err 2 insufficient_funds`
+//! - `err <error>` when it's malformed, the connection staying open
+//!
+//! The connections are read on threads of their own while the engine stays on the
+//! calling thread, taking the transactions one at a time over a channel. They're
+//! accepted over tcp, or over a unix socket for the producers on the same host, which
+//! the permissions of its file, or of its directory, restrict.
+
+use crate::{
+    check_headers, json_tx, CsvParser, Engine, ReadOptions, Store, Tx, TxError, TxOutcome,
+    TX_COLUMNS,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    iter,
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+/// How often the listener checks whether it should stop while no transaction comes in
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A transaction for the engine, run on its thread
+type Call<S> = Box<dyn FnOnce(&mut Engine<S>) + Send>;
+
+/// Where `listen` accepts its connections, e.g. `tcp://0.0.0.0:9000` or
+/// `unix:///run/txprocessor.sock`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// the path of the socket, unix only
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once("://") {
+            Some(("tcp", addr)) => {
+                Ok(ListenAddr::Tcp(addr.parse().with_context(|| {
+                    format!("{} is an invalid address", addr)
+                })?))
+            }
+            Some(("unix", path)) if cfg!(unix) => {
+                if path.is_empty() {
+                    bail!("expected the path of the socket, e.g. unix:///run/txprocessor.sock");
+                }
+                Ok(ListenAddr::Unix(PathBuf::from(path)))
+            }
+            Some(("unix", _)) => bail!("unix sockets are only supported on unix"),
+            Some((scheme, _)) => bail!("{} isn't a supported scheme, expected tcp or unix", scheme),
+            None => bail!("expected a url, e.g. tcp://0.0.0.0:9000"),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "tcp://{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// Applies the transactions of the connections to `addr` until `should_stop` says
+/// otherwise, e.g. on SIGINT. A connection still open is then answered with an error
+/// on its next line. A unix socket is created, replacing the one a previous run left
+/// behind, and removed once stopped.
+pub fn listen<S: Store + 'static>(
+    engine: &mut Engine<S>,
+    addr: &ListenAddr,
+    options: &ReadOptions,
+    should_stop: impl Fn() -> bool,
+) -> Result<()> {
+    let (calls, engine_calls) = mpsc::channel::<Call<S>>();
+    match addr {
+        ListenAddr::Tcp(socket_addr) => {
+            let listener = TcpListener::bind(socket_addr)
+                .with_context(|| format!("failed listening on {}", addr))?;
+            let connections = iter::repeat_with(move || {
+                let (stream, peer) = listener.accept()?;
+                Ok((
+                    BufReader::new(stream.try_clone()?),
+                    stream,
+                    peer.to_string(),
+                ))
+            });
+            spawn_listener(connections, &calls, options)?;
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("failed listening on {}", addr))?;
+            let connections = iter::repeat_with(move || {
+                let (stream, peer) = listener.accept()?;
+                let peer = format!("{:?}", peer);
+                Ok((BufReader::new(stream.try_clone()?), stream, peer))
+            });
+            spawn_listener(connections, &calls, options)?;
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(_) => bail!("unix sockets are only supported on unix"),
+    }
+    tracing::info!(%addr, "listening");
+
+    while !should_stop() {
+        match engine_calls.recv_timeout(POLL_INTERVAL) {
+            Ok(call) => call(engine),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    #[cfg(unix)]
+    if let ListenAddr::Unix(path) = addr {
+        fs::remove_file(path).with_context(|| format!("failed removing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Removes the socket at `path` a previous run left behind, one still accepting
+/// connections fails the run, and any other file is left for binding to fail on
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if UnixStream::connect(path).is_ok() {
+                bail!("{} is already listened on", path.display());
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("failed removing the stale socket {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Accepts the connections on a thread of its own, which is left blocked accepting
+/// to end with the process
+fn spawn_listener<S: Store + 'static, R, W>(
+    connections: impl Iterator<Item = io::Result<(R, W, String)>> + Send + 'static,
+    calls: &mpsc::Sender<Call<S>>,
+    options: &ReadOptions,
+) -> Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let (calls, options) = (calls.clone(), options.clone());
+    thread::Builder::new()
+        .name("listener".to_string())
+        .spawn(move || {
+            for connection in connections {
+                let connected = connection.and_then(|(reader, writer, peer)| {
+                    spawn_connection(reader, writer, peer, &calls, &options)
+                });
+                if let Err(e) = connected {
+                    tracing::warn!(error = %e, "failed accepting a connection");
+                }
+            }
+        })
+        .context("failed spawning the listener")?;
+    Ok(())
+}
+
+fn spawn_connection<S: Store + 'static>(
+    reader: impl BufRead + Send + 'static,
+    writer: impl Write + Send + 'static,
+    peer: String,
+    calls: &mpsc::Sender<Call<S>>,
+    options: &ReadOptions,
+) -> io::Result<()> {
+    let (calls, options) = (calls.clone(), options.clone());
+    thread::Builder::new()
+        .name("connection".to_string())
+        .spawn(move || {
+            tracing::debug!(%peer, "connected");
+            if let Err(e) = answer_lines(reader, writer, &calls, &options) {
+                tracing::warn!(%peer, error = %e, "connection failed");
+            }
+        })?;
+    Ok(())
+}
+
+/// Answers each line of a connection until it's closed, or the store fails
+fn answer_lines<S: Store + 'static>(
+    reader: impl BufRead,
+    mut writer: impl Write,
+    calls: &mpsc::Sender<Call<S>>,
+    options: &ReadOptions,
+) -> io::Result<()> {
+    let mut parser = LineParser::new(options);
+    for (i, line) in reader.lines().enumerate() {
+        let (answer, failed) = match parser.parse(&line?, i + 1) {
+            Ok(Line::Blank) => continue,
+            Ok(Line::Header) => ("ack".to_string(), false),
+            Ok(Line::Tx(tx)) => submit(tx, calls),
+            Err(e) => (format!("err {:#}", e), false),
+        };
+        // an answer per line, whatever the errors it quotes
+        writeln!(writer, "{}", answer.replace(['\r', '\n'], " "))?;
+        writer.flush()?;
+        if failed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The answer to a transaction once the engine processed it, and whether the store
+/// failed, which ends the connection
+fn submit<S: Store + 'static>(tx: Tx, calls: &mpsc::Sender<Call<S>>) -> (String, bool) {
+    let id = tx.id;
+    let (reply, replied) = mpsc::sync_channel(1);
+    let call: Call<S> = Box::new(move |engine| {
+        let _ = reply.send(engine.submit(tx));
+    });
+    match calls.send(call).ok().and_then(|_| replied.recv().ok()) {
+        Some(Ok(outcome @ (TxOutcome::Applied | TxOutcome::Skipped))) => {
+            (format!("ack {} {}", id, outcome), false)
+        }
+        Some(Ok(outcome)) => (format!("err {} {}", id, outcome), false),
+        Some(Err(e @ TxError::Storage(_))) => (format!("err {}", e), true),
+        Some(Err(e)) => (format!("err {} {:#}", id, anyhow::Error::from(e)), false),
+        None => ("err the server is stopping".to_string(), true),
+    }
+}
+
+/// What a line of a connection is
+enum Line {
+    Blank,
+    /// the csv columns of the records after it
+    Header,
+    Tx(Tx),
+}
+
+/// Reads the lines of a connection, an object being read as a line of ndjson and any
+/// other line as a csv record. The records have the columns of the header line sent
+/// ahead of them, if any, or else `type,client,tx,amount,to`.
+struct LineParser<'o> {
+    csv: CsvParser,
+    options: &'o ReadOptions,
+}
+
+impl<'o> LineParser<'o> {
+    fn new(options: &'o ReadOptions) -> Self {
+        LineParser {
+            csv: CsvParser::new(
+                csv::StringRecord::from(TX_COLUMNS.to_vec()),
+                options.precision,
+            ),
+            options,
+        }
+    }
+
+    fn parse(&mut self, line: &str, number: usize) -> Result<Line> {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            return Ok(Line::Blank);
+        }
+
+        if line.trim_start().starts_with('{') {
+            let parse = || {
+                let element: serde_json::Value = serde_json::from_str(line)?;
+                if let Some(fields) = element.as_object() {
+                    check_headers(fields.keys().map(String::as_str), self.options)?;
+                }
+                json_tx(element, self.options.precision)
+            };
+            return parse()
+                .with_context(|| format!("invalid transaction on line {}", number))
+                .map(Line::Tx);
+        }
+
+        let mut record = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(line.as_bytes())
+            .into_records()
+            .next()
+            .ok_or_else(|| anyhow!("invalid transaction on line {}", number))?;
+        if let Ok(record) = &record {
+            if record.get(0).map(str::trim) == Some("type") {
+                check_headers(record, self.options)?;
+                self.csv = CsvParser::new(record.clone(), self.options.precision);
+                return Ok(Line::Header);
+            }
+        }
+        if let Ok(record) = &mut record {
+            let mut position = csv::Position::new();
+            position.set_line(number as u64);
+            record.set_position(Some(position));
+        }
+        Ok(Line::Tx(
+            self.csv.parse(record).map_err(anyhow::Error::from)?,
+        ))
+    }
+}
@@ -0,0 +1,162 @@
+//! The prometheus metrics of an engine, served over http for scraping. The
+//! transactions are counted, and timed, as they're processed, the account gauges
+//! are set from the stored accounts once, then moved by each account a transaction
+//! changes.
+
+use crate::{Account, Amount, TxType, AMOUNT_SCALE};
+use anyhow::{anyhow, Context, Result};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Response, Server};
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    transactions: IntCounterVec,
+    duration: HistogramVec,
+    available: Gauge,
+    held: Gauge,
+    locked_accounts: IntGauge,
+    /// the available and held funds across the accounts in minor units, the gauges are
+    /// set from so moving them doesn't drift
+    funds: Arc<Mutex<(i128, i128)>>,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let transactions = IntCounterVec::new(
+            Opts::new(
+                "txprocessor_transactions_total",
+                "Transactions processed, by type and outcome",
+            ),
+            &["type", "outcome"],
+        )?;
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "txprocessor_transaction_duration_seconds",
+                "Time taken processing a transaction, by type",
+            ),
+            &["type"],
+        )?;
+        let available = Gauge::new(
+            "txprocessor_available_funds",
+            "Funds available across the accounts",
+        )?;
+        let held = Gauge::new("txprocessor_held_funds", "Funds held across the accounts")?;
+        let locked_accounts = IntGauge::new(
+            "txprocessor_locked_accounts",
+            "Accounts locked by a chargeback",
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(transactions.clone()))?;
+        registry.register(Box::new(duration.clone()))?;
+        registry.register(Box::new(available.clone()))?;
+        registry.register(Box::new(held.clone()))?;
+        registry.register(Box::new(locked_accounts.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            transactions,
+            duration,
+            available,
+            held,
+            locked_accounts,
+            funds: Arc::default(),
+        })
+    }
+
+    /// Counts a transaction by its outcome, `error` when it failed, and times it
+    pub(crate) fn observe(&self, tx_type: TxType, outcome: &str, elapsed: Duration) {
+        let tx_type = tx_type.to_string();
+        self.transactions
+            .with_label_values(&[&tx_type, outcome])
+            .inc();
+        self.duration
+            .with_label_values(&[&tx_type])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub(crate) fn set_accounts(&self, accounts: &[Account]) {
+        let units = |amount: fn(&Account) -> Amount| {
+            accounts
+                .iter()
+                .map(|account| amount(account).minor_units() as i128)
+                .sum()
+        };
+        let mut funds = self.funds.lock().expect("a poisoned lock");
+        *funds = (
+            units(|account| account.available),
+            units(|account| account.held),
+        );
+        self.set_funds(*funds);
+        self.locked_accounts
+            .set(accounts.iter().filter(|account| account.locked).count() as i64);
+    }
+
+    /// Moves the account gauges by what a transaction changed of an account, `None` for
+    /// one which didn't exist before it or was closed by it
+    pub(crate) fn account_changed(&self, before: Option<&Account>, after: Option<&Account>) {
+        let units = |account: Option<&Account>| {
+            account.map_or((0, 0, 0), |account| {
+                (
+                    account.available.minor_units() as i128,
+                    account.held.minor_units() as i128,
+                    account.locked as i64,
+                )
+            })
+        };
+        let (before, after) = (units(before), units(after));
+        let mut funds = self.funds.lock().expect("a poisoned lock");
+        funds.0 += after.0 - before.0;
+        funds.1 += after.1 - before.1;
+        self.set_funds(*funds);
+        self.locked_accounts.add(after.2 - before.2);
+    }
+
+    fn set_funds(&self, (available, held): (i128, i128)) {
+        self.available.set(available as f64 / AMOUNT_SCALE as f64);
+        self.held.set(held as f64 / AMOUNT_SCALE as f64);
+    }
+
+    /// The metrics in the prometheus text format
+    pub fn render(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .context("failed encoding metrics")?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    /// Serves the metrics on every path of `addr` from a background thread, for as
+    /// long as the process runs
+    pub fn serve(&self, addr: SocketAddr) -> Result<()> {
+        let server = Server::http(addr)
+            .map_err(|e| anyhow!("failed listening for metrics on {}: {}", addr, e))?;
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = match metrics.render() {
+                    Ok(body) => Response::from_string(body).with_header(
+                        "Content-Type: text/plain; version=0.0.4"
+                            .parse::<Header>()
+                            .expect("a valid header"),
+                    ),
+                    Err(e) => Response::from_string(format!("{:#}", e)).with_status_code(500),
+                };
+                if let Err(e) = request.respond(response) {
+                    eprintln!("failed serving metrics: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
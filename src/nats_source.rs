@@ -0,0 +1,89 @@
+//! The nats jetstream source of `consume-nats`, applying the transactions of a stream
+//! through a durable pull consumer, so the server keeps track of what was applied
+//! across runs. Each message is read as an input of its own, e.g. an ndjson object,
+//! and only acked once the store committed its transactions, an unacked message being
+//! redelivered once its ack wait is over. A redelivered deposit or withdrawal is
+//! rejected as a `duplicate_tx`.
+
+use crate::{process_message, Engine, InputFormat, ReadOptions, Store};
+use anyhow::{anyhow, Context, Result};
+use async_nats::jetstream::{
+    self,
+    consumer::{pull, AckPolicy},
+};
+use futures_lite::StreamExt;
+use std::time::Duration;
+
+/// How often the consumer checks whether it should stop while no message comes in
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Where `consume-nats` reads the transactions from
+#[derive(Debug, Clone)]
+pub struct NatsOptions {
+    /// e.g. `nats://localhost:4222`
+    pub url: String,
+    /// the jetstream stream, it has to exist already
+    pub stream: String,
+    /// the durable consumer, created on the stream if it doesn't exist
+    pub durable: String,
+}
+
+/// Applies the messages of the stream until `should_stop` says otherwise, e.g. on
+/// SIGINT, a message being applied is finished first. Its malformed transactions are
+/// handled by the `on_error` of the engine's config, a message which can't be read at
+/// all being one of them. Failing the run leaves the message unacked.
+pub fn consume_nats<S: Store>(
+    engine: &mut Engine<S>,
+    nats: &NatsOptions,
+    format: InputFormat,
+    options: &ReadOptions,
+    should_stop: impl Fn() -> bool,
+) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed starting the nats runtime")?;
+    runtime.block_on(async {
+        let client = async_nats::connect(&nats.url)
+            .await
+            .with_context(|| format!("failed connecting to {}", nats.url))?;
+        let stream = jetstream::new(client)
+            .get_stream(&nats.stream)
+            .await
+            .with_context(|| format!("failed getting the {} stream", nats.stream))?;
+        let config = pull::Config {
+            durable_name: Some(nats.durable.clone()),
+            ack_policy: AckPolicy::Explicit,
+            ..pull::Config::default()
+        };
+        let mut messages = stream
+            .get_or_create_consumer(&nats.durable, config)
+            .await
+            .with_context(|| format!("failed getting the {} consumer", nats.durable))?
+            .messages()
+            .await
+            .context("failed consuming")?;
+        tracing::info!(stream = %nats.stream, durable = %nats.durable, "consuming");
+
+        while !should_stop() {
+            let message = match tokio::time::timeout(POLL_INTERVAL, messages.next()).await {
+                Ok(Some(message)) => message.map_err(|e| anyhow!("failed consuming: {}", e))?,
+                Ok(None) => return Err(anyhow!("the server ended the consumer")),
+                Err(_) => continue,
+            };
+
+            process_message(engine, &message.payload, format, options).map_err(|e| {
+                anyhow!(
+                    "failed applying message of {}: {:#}",
+                    message.subject,
+                    anyhow::Error::from(e)
+                )
+            })?;
+            message
+                .ack()
+                .await
+                .map_err(|e| anyhow!("failed acking the message: {}", e))?;
+        }
+        Ok(())
+    })
+}
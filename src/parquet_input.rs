@@ -0,0 +1,34 @@
+//! The parquet reader, for transaction histories archived in a data lake. The
+//! columns are mapped to the transaction fields by name, as the csv ones are, and
+//! the rows are read a row group at a time as the stream is pulled.
+
+use crate::{check_headers, json_tx, ReadOptions, Tx, TxError};
+use anyhow::{Context, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::fs::File;
+
+/// Reads the transactions of a parquet file. The schema is checked up front, a
+/// malformed row only fails once it's reached. The amount column is nullable, a
+/// null reads as the empty amount of a dispute, resolve or chargeback.
+pub fn read_parquet_txs(
+    file: File,
+    options: &ReadOptions,
+) -> Result<impl Iterator<Item = Result<Tx, TxError>>> {
+    let reader = SerializedFileReader::new(file).context("failed opening parquet file")?;
+    let columns = reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .root_schema();
+    check_headers(columns.get_fields().iter().map(|c| c.name()), options)?;
+
+    // rows go through the json representation to share its amount handling
+    let precision = options.precision;
+    Ok(reader
+        .into_iter()
+        .map(move |row| {
+            let row = row.context("failed reading parquet row")?;
+            json_tx(row.to_json_value(), precision)
+        })
+        .map(|tx| tx.map_err(TxError::parse)))
+}
@@ -0,0 +1,67 @@
+//! The parquet writer of the account report, for snapshots dropped straight into
+//! a data lake. Amounts are decimals of scale four stored as their minor units.
+
+use crate::{Account, AMOUNT_DECIMALS};
+use anyhow::{Context, Result};
+use parquet::{
+    data_type::{BoolType, Int32Type, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use std::{io::Write, sync::Arc};
+
+/// Writes the accounts into `wtr` as a parquet file of a single row group. The
+/// accounts are bounded by the client id space, so the file is built in memory.
+pub(crate) fn write_parquet(accounts: Vec<Account>, mut wtr: impl Write) -> Result<()> {
+    let schema = parse_message_type(&format!(
+        "message account {{
+            required int32 client_id (INTEGER(16,false));
+            required int64 available (DECIMAL(18,{decimals}));
+            required int64 held (DECIMAL(18,{decimals}));
+            required int64 total (DECIMAL(18,{decimals}));
+            required boolean locked;
+            required boolean frozen;
+        }}",
+        decimals = AMOUNT_DECIMALS
+    ))?;
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(Vec::new(), Arc::new(schema), properties)?;
+
+    let client_ids: Vec<i32> = accounts.iter().map(|acc| acc.client_id.into()).collect();
+    let available: Vec<i64> = accounts.iter().map(|acc| acc.available.0).collect();
+    let held: Vec<i64> = accounts.iter().map(|acc| acc.held.0).collect();
+    let total: Vec<i64> = accounts.iter().map(|acc| acc.total.0).collect();
+    let locked: Vec<bool> = accounts.iter().map(|acc| acc.locked).collect();
+    let frozen: Vec<bool> = accounts.iter().map(|acc| acc.frozen).collect();
+
+    // the columns come in the order of the schema
+    let mut row_group = writer.next_row_group()?;
+    let mut column = row_group
+        .next_column()?
+        .context("missing client_id column")?;
+    column
+        .typed::<Int32Type>()
+        .write_batch(&client_ids, None, None)?;
+    column.close()?;
+    for (name, amounts) in [("available", available), ("held", held), ("total", total)] {
+        let mut column = row_group
+            .next_column()?
+            .with_context(|| format!("missing {} column", name))?;
+        column
+            .typed::<Int64Type>()
+            .write_batch(&amounts, None, None)?;
+        column.close()?;
+    }
+    for (name, flags) in [("locked", locked), ("frozen", frozen)] {
+        let mut column = row_group
+            .next_column()?
+            .with_context(|| format!("missing {} column", name))?;
+        column.typed::<BoolType>().write_batch(&flags, None, None)?;
+        column.close()?;
+    }
+    row_group.close()?;
+
+    let file = writer.into_inner()?;
+    wtr.write_all(&file).context("failed writing parquet")?;
+    wtr.flush().context("failed flushing into buffer or file")
+}
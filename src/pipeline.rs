@@ -0,0 +1,115 @@
+//! The stages a stream of transactions goes through: read and parsed on a thread
+//! of its own, applied by the engine on the caller's, the accounts reported once
+//! it's drained. The stages are joined by a bounded channel, so the reading and
+//! parsing of the next records overlap with the store writing the current ones,
+//! the order of the records kept.
+
+use crate::{Tx, TxError};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    panic,
+    sync::mpsc::{self, Receiver},
+    thread::{self, JoinHandle},
+    vec,
+};
+
+/// How many parsed records the reading stage gets ahead of the applying one
+pub const READ_AHEAD: usize = 1024;
+
+/// How many records are handed over at once, so the stages don't wake each other up
+/// for every one of them
+const CHUNK: usize = 64;
+
+/// The transactions of an input as the readers stream them
+pub type Txs = Box<dyn Iterator<Item = Result<Tx, TxError>>>;
+
+/// The records of an input read on a thread of their own, in their order
+pub struct ReadStage {
+    chunks: Receiver<Vec<Result<Tx, TxError>>>,
+    chunk: vec::IntoIter<Result<Tx, TxError>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl ReadStage {
+    /// Spawns the stage, `open` opening and reading the input on its thread as the
+    /// readers can't be sent to it. Failing to open it is returned here, before any
+    /// record. Dropping the stage stops the reading at the next records. The records
+    /// are handed over a chunk at a time unless `streaming`, e.g. stdin, so none of
+    /// them waits for the ones after it. At most `read_ahead` records wait for the
+    /// engine, past them the reading blocks until it catches up.
+    pub fn spawn<F>(open: F, read_ahead: usize, streaming: bool) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Txs> + Send + 'static,
+    {
+        let (opened_sender, opened) = mpsc::sync_channel(1);
+        let chunk_size = if streaming {
+            1
+        } else {
+            CHUNK.min(read_ahead).max(1)
+        };
+        let (sender, chunks) = mpsc::sync_channel(read_ahead / chunk_size);
+        let reader = thread::Builder::new()
+            .name("reader".to_string())
+            .spawn(move || {
+                let txs = match open() {
+                    Ok(txs) => txs,
+                    Err(e) => {
+                        let _ = opened_sender.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = opened_sender.send(Ok(()));
+                let mut txs = txs.peekable();
+                while txs.peek().is_some() {
+                    let chunk = txs.by_ref().take(chunk_size).collect();
+                    // the applying stage is gone, e.g. interrupted
+                    if sender.send(chunk).is_err() {
+                        return;
+                    }
+                }
+            })
+            .context("failed spawning the reading stage")?;
+
+        let mut stage = ReadStage {
+            chunks,
+            chunk: Vec::new().into_iter(),
+            reader: Some(reader),
+        };
+        match opened.recv() {
+            Ok(opened) => opened.map(|_| stage),
+            Err(_) => {
+                stage.join();
+                Err(anyhow!("the reading stage ended before opening the input"))
+            }
+        }
+    }
+
+    /// Waits for the reading thread, which already ended, passing its panic on
+    fn join(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            if let Err(panicked) = reader.join() {
+                panic::resume_unwind(panicked);
+            }
+        }
+    }
+}
+
+impl Iterator for ReadStage {
+    type Item = Result<Tx, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.chunk.next() {
+                return Some(record);
+            }
+            match self.chunks.recv() {
+                Ok(chunk) => self.chunk = chunk.into_iter(),
+                // every record was sent, or the reading panicked
+                Err(_) => {
+                    self.join();
+                    return None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,688 @@
+//! The postgres store, for several hosts sharing the state. It keeps the same
+//! tables as the sqlite store and gets the transaction semantics through
+//! [`Records`], every transaction is applied in a serializable postgres one.
+
+use crate::{
+    allows_every_status, tx_status_check, Account, AccountRecord, AccountStatus, Accrual, Amount,
+    AuditEntry, Changes, ClientId, Config, Notification, Records, Store, Tables, Tx, TxFilter,
+    TxId, TxOutcome, TxRecord, DISPUTED_AMOUNT_BACKFILL, SCHEMA_STEPS,
+};
+use anyhow::{anyhow, Context, Result};
+use postgres::{error::SqlState, Client, IsolationLevel, NoTls, Row, Transaction};
+use std::convert::TryFrom;
+
+/// How often a transaction which conflicted with a concurrent one, e.g. of
+/// another host, is retried before giving up
+const CONFLICT_RETRIES: usize = 8;
+
+pub struct PostgresStore {
+    client: Client,
+    tables: Tables,
+}
+
+impl PostgresStore {
+    /// Connects to the database at `url`, e.g. `postgres://user@host/db`, the
+    /// tables are created if they don't exist yet
+    pub fn connect(url: &str, tables: Tables) -> Result<Self> {
+        let mut client =
+            Client::connect(url, NoTls).with_context(|| format!("failed connecting to {}", url))?;
+        migrate_tables(&mut client, &tables)?;
+
+        Ok(PostgresStore { client, tables })
+    }
+
+    /// Runs `f` in a serializable transaction, committed when it succeeds and
+    /// retried when it conflicted with a concurrent one
+    fn in_transaction<T>(&mut self, f: impl Fn(&mut PgRecords) -> Result<T>) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            let dbtx = self
+                .client
+                .build_transaction()
+                .isolation_level(IsolationLevel::Serializable)
+                .start()
+                .context("failed starting transaction")?;
+            let mut records = PgRecords {
+                dbtx,
+                tables: &self.tables,
+            };
+
+            let result = match f(&mut records) {
+                Ok(out) => records
+                    .dbtx
+                    .commit()
+                    .context("failed committing transaction")
+                    .map(|_| out),
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if retries < CONFLICT_RETRIES && is_conflict(&e) => retries += 1,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether `e` comes from a transaction postgres aborted for a concurrent one
+fn is_conflict(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<postgres::Error>())
+        .filter_map(|e| e.code())
+        .any(|code| {
+            *code == SqlState::T_R_SERIALIZATION_FAILURE
+                || *code == SqlState::T_R_DEADLOCK_DETECTED
+                || *code == SqlState::UNIQUE_VIOLATION
+        })
+}
+
+fn migrate_tables(client: &mut Client, tables: &Tables) -> Result<()> {
+    let mut dbtx = client.transaction()?;
+    dbtx.batch_execute(&tables.sql(&format!("CREATE TABLE IF NOT EXISTS {{tx}} (id BIGINT PRIMARY KEY, tx_type TEXT, client_id INTEGER, amount BIGINT, status TEXT DEFAULT 'processed' CHECK ({}), disputed_amount BIGINT DEFAULT 0, ingested_at BIGINT, timestamp BIGINT);", tx_status_check())))
+        .context("failed migrating tx table")?;
+    // tx tables from before the latest statuses have a status check rejecting them
+    let check: Option<String> = dbtx
+        .query_opt(
+            "SELECT pg_get_constraintdef(oid) FROM pg_constraint WHERE conrelid = $1::text::regclass AND contype = 'c' AND pg_get_constraintdef(oid) LIKE '%in_dispute%';",
+            &[&tables.tx],
+        )
+        .context("failed inspecting tx table")?
+        .map(|row| row.try_get(0))
+        .transpose()?;
+    if matches!(check, Some(check) if !allows_every_status(&check)) {
+        dbtx.batch_execute(&tables.sql(&format!("ALTER TABLE {{tx}} DROP CONSTRAINT IF EXISTS {{tx}}_status_check; ALTER TABLE {{tx}} ADD CONSTRAINT {{tx}}_status_check CHECK ({});", tx_status_check())))
+            .context("failed migrating tx status constraint")?;
+    }
+    dbtx.batch_execute(&tables.sql("CREATE TABLE IF NOT EXISTS {account} (id INTEGER PRIMARY KEY, available_amount BIGINT, held_amount BIGINT, total_amount BIGINT, locked BOOLEAN, status TEXT DEFAULT 'active' CHECK (status IN ('active', 'blocked', 'inactive')), frozen BOOLEAN DEFAULT false, overdraft_limit BIGINT DEFAULT 0, withdrawn_amount BIGINT DEFAULT 0, withdrawal_day BIGINT DEFAULT 0);"))
+        .context("failed migrating account table")?;
+    dbtx.batch_execute(&tables.sql("CREATE TABLE IF NOT EXISTS {rejected_tx} (seq BIGSERIAL PRIMARY KEY, tx_id BIGINT, tx_type TEXT, client_id INTEGER, amount BIGINT, reason TEXT, to_client INTEGER, timestamp BIGINT);"))
+        .context("failed migrating rejected_tx table")?;
+    dbtx.batch_execute(&tables.sql("CREATE TABLE IF NOT EXISTS {event} (seq BIGSERIAL PRIMARY KEY, tx_id BIGINT, tx_type TEXT, client_id INTEGER, amount BIGINT, to_client INTEGER, timestamp BIGINT);"))
+        .context("failed migrating event table")?;
+    dbtx.batch_execute(&tables.sql("CREATE TABLE IF NOT EXISTS {audit_log} (seq BIGSERIAL PRIMARY KEY, tx_id BIGINT, tx_type TEXT, client_id INTEGER, amount BIGINT, to_client INTEGER, outcome TEXT, available_amount BIGINT, held_amount BIGINT, total_amount BIGINT, timestamp BIGINT);"))
+        .context("failed migrating audit_log table")?;
+    dbtx.batch_execute(&tables.sql(
+        "CREATE TABLE IF NOT EXISTS {checkpoint} (input_hash TEXT PRIMARY KEY, processed BIGINT);",
+    ))
+    .context("failed migrating checkpoint table")?;
+    dbtx.batch_execute(&tables.sql(
+        "CREATE TABLE IF NOT EXISTS {outbox} (seq BIGSERIAL PRIMARY KEY, url TEXT, payload TEXT);",
+    ))
+    .context("failed migrating outbox table")?;
+    dbtx.batch_execute(&tables.sql("CREATE TABLE IF NOT EXISTS {accrual} (as_of TEXT PRIMARY KEY, rate TEXT, first_tx BIGINT, accounts BIGINT, interest_amount BIGINT, completed BOOLEAN);"))
+        .context("failed migrating accrual table")?;
+    // logs from before transfers get the destination column
+    dbtx.batch_execute(&tables.sql("ALTER TABLE {rejected_tx} ADD COLUMN IF NOT EXISTS to_client INTEGER; ALTER TABLE {event} ADD COLUMN IF NOT EXISTS to_client INTEGER;"))
+        .context("failed adding the transfer destination")?;
+    // tx tables from before partial disputes get the disputed part
+    dbtx.batch_execute(
+        &tables.sql("ALTER TABLE {tx} ADD COLUMN IF NOT EXISTS disputed_amount BIGINT DEFAULT 0;"),
+    )
+    .context("failed adding the disputed amount")?;
+    dbtx.batch_execute(&tables.sql(DISPUTED_AMOUNT_BACKFILL))
+        .context("failed backfilling disputed amounts")?;
+    // their ingest time is unknown, they're never outside the dispute window
+    dbtx.batch_execute(
+        &tables.sql("ALTER TABLE {tx} ADD COLUMN IF NOT EXISTS ingested_at BIGINT;"),
+    )
+    .context("failed adding the ingest time")?;
+    // and before timestamps none, the transactions they hold happened when they were stored
+    dbtx.batch_execute(&tables.sql("ALTER TABLE {tx} ADD COLUMN IF NOT EXISTS timestamp BIGINT; ALTER TABLE {rejected_tx} ADD COLUMN IF NOT EXISTS timestamp BIGINT; ALTER TABLE {event} ADD COLUMN IF NOT EXISTS timestamp BIGINT; ALTER TABLE {audit_log} ADD COLUMN IF NOT EXISTS timestamp BIGINT;"))
+        .context("failed adding the timestamp")?;
+    // account tables from before freezes get the flag, none of them frozen
+    dbtx.batch_execute(
+        &tables.sql("ALTER TABLE {account} ADD COLUMN IF NOT EXISTS frozen BOOLEAN DEFAULT false;"),
+    )
+    .context("failed adding the frozen flag")?;
+    // and before overdrafts the limit, none of them can be overdrawn
+    dbtx.batch_execute(
+        &tables.sql(
+            "ALTER TABLE {account} ADD COLUMN IF NOT EXISTS overdraft_limit BIGINT DEFAULT 0;",
+        ),
+    )
+    .context("failed adding the overdraft limit")?;
+    // and before daily withdrawal limits none withdrawn
+    dbtx.batch_execute(&tables.sql(
+        "ALTER TABLE {account} ADD COLUMN IF NOT EXISTS withdrawn_amount BIGINT DEFAULT 0, ADD COLUMN IF NOT EXISTS withdrawal_day BIGINT DEFAULT 0;",
+    ))
+    .context("failed adding the daily withdrawals")?;
+
+    dbtx.batch_execute(
+        &tables.sql("CREATE TABLE IF NOT EXISTS {schema_version} (version INTEGER NOT NULL);"),
+    )
+    .context("failed migrating schema_version table")?;
+    let version: Option<i32> = dbtx
+        .query_one(
+            &tables.sql("SELECT max(version) FROM {schema_version};"),
+            &[],
+        )
+        .and_then(|row| row.try_get(0))
+        .context("failed reading schema version")?;
+    for (step, sql) in SCHEMA_STEPS
+        .iter()
+        .enumerate()
+        .skip(version.unwrap_or(0) as usize)
+    {
+        let version = step as i32 + 1;
+        dbtx.batch_execute(&tables.sql(sql))
+            .with_context(|| format!("failed migrating schema to version {}", version))?;
+        dbtx.execute(
+            &tables.sql("INSERT INTO {schema_version} (version) VALUES ($1);"),
+            &[&version],
+        )
+        .context("failed recording schema version")?;
+    }
+
+    dbtx.commit().context("failed committing migrations")
+}
+
+/// The records as seen from within a single postgres transaction
+struct PgRecords<'a> {
+    dbtx: Transaction<'a>,
+    tables: &'a Tables,
+}
+
+fn client_id_of(row: &Row, idx: usize) -> Result<ClientId> {
+    let client_id: i32 = row.try_get(idx)?;
+    ClientId::try_from(client_id).map_err(|_| anyhow!("{} is an invalid client id", client_id))
+}
+
+fn tx_id_of(row: &Row, idx: usize) -> Result<TxId> {
+    let id: i64 = row.try_get(idx)?;
+    TxId::try_from(id).map_err(|_| anyhow!("{} is an invalid tx id", id))
+}
+
+fn parse_of<T: std::str::FromStr>(row: &Row, idx: usize) -> Result<T> {
+    let value: String = row.try_get(idx)?;
+    value
+        .parse()
+        .map_err(|_| anyhow!("{} is an invalid {}", value, row.columns()[idx].name()))
+}
+
+/// A logged transaction of the columns `tx_id, tx_type, client_id, amount, to_client,
+/// timestamp`
+fn tx_of(row: &Row) -> Result<Tx> {
+    Ok(Tx {
+        id: tx_id_of(row, 0)?,
+        tx_type: parse_of(row, 1)?,
+        client_id: client_id_of(row, 2)?,
+        amount: row
+            .try_get::<_, Option<i64>>(3)?
+            .map(Amount::from_minor_units),
+        to_client: match row.try_get::<_, Option<i32>>(4)? {
+            Some(_) => Some(client_id_of(row, 4)?),
+            None => None,
+        },
+        timestamp: row.try_get(5)?,
+    })
+}
+
+/// A stored transaction of the columns `id, tx_type, client_id, amount, status,
+/// disputed_amount, ingested_at, timestamp`
+fn txrecord_of(row: &Row) -> Result<(TxId, TxRecord)> {
+    Ok((
+        tx_id_of(row, 0)?,
+        TxRecord {
+            tx_type: parse_of(row, 1)?,
+            client_id: client_id_of(row, 2)?,
+            amount: Amount::from_minor_units(row.try_get(3)?),
+            status: parse_of(row, 4)?,
+            disputed: Amount::from_minor_units(row.try_get(5)?),
+            ingested_at: row.try_get(6)?,
+            timestamp: row.try_get(7)?,
+        },
+    ))
+}
+
+fn account_of(row: &Row) -> Result<(ClientId, AccountRecord)> {
+    Ok((
+        client_id_of(row, 0)?,
+        AccountRecord {
+            available: Amount::from_minor_units(row.try_get(1)?),
+            held: Amount::from_minor_units(row.try_get(2)?),
+            total: Amount::from_minor_units(row.try_get(3)?),
+            status: parse_of(row, 4)?,
+            frozen: row.try_get(5)?,
+            overdraft_limit: Amount::from_minor_units(row.try_get(6)?),
+            withdrawn: Amount::from_minor_units(row.try_get(7)?),
+            withdrawal_day: row.try_get(8)?,
+        },
+    ))
+}
+
+impl Records for PgRecords<'_> {
+    fn get_account(&mut self, client_id: ClientId) -> Result<Option<AccountRecord>> {
+        let sql = self.tables.sql("SELECT id, available_amount, held_amount, total_amount, status, frozen, overdraft_limit, withdrawn_amount, withdrawal_day FROM {account} WHERE id = $1;");
+        self.dbtx
+            .query_opt(sql.as_str(), &[&i32::from(client_id)])
+            .context("failed reading account")?
+            .map(|row| account_of(&row).map(|(_, account)| account))
+            .transpose()
+    }
+
+    fn get_tx(&mut self, id: TxId) -> Result<Option<TxRecord>> {
+        let sql = self.tables.sql(
+            "SELECT id, tx_type, client_id, amount, status, disputed_amount, ingested_at, timestamp FROM {tx} WHERE id = $1;",
+        );
+        self.dbtx
+            .query_opt(sql.as_str(), &[&i64::from(id)])
+            .context("failed reading tx")?
+            .map(|row| txrecord_of(&row).map(|(_, txrecord)| txrecord))
+            .transpose()
+    }
+
+    fn get_checkpoint(&mut self, input_hash: &str) -> Result<Option<u64>> {
+        let sql = self
+            .tables
+            .sql("SELECT processed FROM {checkpoint} WHERE input_hash = $1;");
+        self.dbtx
+            .query_opt(sql.as_str(), &[&input_hash])
+            .context("failed reading checkpoint")?
+            .map(|row| {
+                let processed: i64 = row.try_get(0)?;
+                u64::try_from(processed)
+                    .map_err(|_| anyhow!("{} is an invalid checkpoint", processed))
+            })
+            .transpose()
+    }
+
+    fn commit(&mut self, changes: Changes) -> Result<()> {
+        for (client_id, account) in changes.accounts {
+            let locked = account.status == AccountStatus::Blocked;
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {account} (id, available_amount, held_amount, total_amount, locked, status, frozen, overdraft_limit, withdrawn_amount, withdrawal_day) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) ON CONFLICT (id) DO UPDATE SET available_amount = EXCLUDED.available_amount, held_amount = EXCLUDED.held_amount, total_amount = EXCLUDED.total_amount, locked = EXCLUDED.locked, status = EXCLUDED.status, frozen = EXCLUDED.frozen, overdraft_limit = EXCLUDED.overdraft_limit, withdrawn_amount = EXCLUDED.withdrawn_amount, withdrawal_day = EXCLUDED.withdrawal_day;").as_str(),
+                    &[
+                        &i32::from(client_id),
+                        &account.available.minor_units(),
+                        &account.held.minor_units(),
+                        &account.total.minor_units(),
+                        &locked,
+                        &account.status.to_string(),
+                        &account.frozen,
+                        &account.overdraft_limit.minor_units(),
+                        &account.withdrawn.minor_units(),
+                        &account.withdrawal_day,
+                    ],
+                )
+                .with_context(|| format!("failed writing account {}", client_id))?;
+        }
+
+        if let Some((id, txrecord)) = changes.tx {
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {tx} (id, tx_type, client_id, amount, status, disputed_amount, ingested_at, timestamp) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (id) DO UPDATE SET tx_type = EXCLUDED.tx_type, status = EXCLUDED.status, disputed_amount = EXCLUDED.disputed_amount;").as_str(),
+                    &[
+                        &i64::from(id),
+                        &txrecord.tx_type.to_string(),
+                        &i32::from(txrecord.client_id),
+                        &txrecord.amount.minor_units(),
+                        &txrecord.status.to_string(),
+                        &txrecord.disputed.minor_units(),
+                        &txrecord.ingested_at,
+                        &txrecord.timestamp,
+                    ],
+                )
+                .with_context(|| format!("failed writing tx {}", id))?;
+        }
+
+        if let Some((tx, reason)) = changes.rejected {
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {rejected_tx} (tx_id, tx_type, client_id, amount, reason, to_client, timestamp) VALUES ($1, $2, $3, $4, $5, $6, $7);").as_str(),
+                    &[
+                        &i64::from(tx.id),
+                        &tx.tx_type.to_string(),
+                        &i32::from(tx.client_id),
+                        &tx.amount.map(Amount::minor_units),
+                        &reason.to_string(),
+                        &tx.to_client.map(i32::from),
+                        &tx.timestamp,
+                    ],
+                )
+                .with_context(|| format!("failed recording rejected tx {}", tx.id))?;
+        }
+
+        if let Some(tx) = changes.event {
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {event} (tx_id, tx_type, client_id, amount, to_client, timestamp) VALUES ($1, $2, $3, $4, $5, $6);").as_str(),
+                    &[
+                        &i64::from(tx.id),
+                        &tx.tx_type.to_string(),
+                        &i32::from(tx.client_id),
+                        &tx.amount.map(Amount::minor_units),
+                        &tx.to_client.map(i32::from),
+                        &tx.timestamp,
+                    ],
+                )
+                .with_context(|| format!("failed recording event of tx {}", tx.id))?;
+        }
+
+        if let Some(entry) = changes.audit {
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {audit_log} (tx_id, tx_type, client_id, amount, to_client, timestamp, outcome, available_amount, held_amount, total_amount) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);").as_str(),
+                    &[
+                        &i64::from(entry.tx.id),
+                        &entry.tx.tx_type.to_string(),
+                        &i32::from(entry.tx.client_id),
+                        &entry.tx.amount.map(Amount::minor_units),
+                        &entry.tx.to_client.map(i32::from),
+                        &entry.tx.timestamp,
+                        &entry.outcome.to_string(),
+                        &entry.available.minor_units(),
+                        &entry.held.minor_units(),
+                        &entry.total.minor_units(),
+                    ],
+                )
+                .with_context(|| format!("failed auditing tx {}", entry.tx.id))?;
+        }
+
+        if let Some((input_hash, processed)) = changes.checkpoint {
+            let processed = i64::try_from(processed)
+                .map_err(|_| anyhow!("{} is an invalid checkpoint", processed))?;
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {checkpoint} (input_hash, processed) VALUES ($1, $2) ON CONFLICT (input_hash) DO UPDATE SET processed = EXCLUDED.processed;").as_str(),
+                    &[&input_hash, &processed],
+                )
+                .context("failed saving checkpoint")?;
+        }
+
+        for notification in changes.notifications {
+            self.dbtx
+                .execute(
+                    self.tables
+                        .sql("INSERT INTO {outbox} (url, payload) VALUES ($1, $2);")
+                        .as_str(),
+                    &[&notification.url, &notification.payload],
+                )
+                .context("failed queueing notification")?;
+        }
+        if let Some(seq) = changes.notified {
+            let seq = i64::try_from(seq).map_err(|_| anyhow!("{} is an invalid seq", seq))?;
+            self.dbtx
+                .execute(
+                    self.tables
+                        .sql("DELETE FROM {outbox} WHERE seq = $1;")
+                        .as_str(),
+                    &[&seq],
+                )
+                .context("failed dropping a delivered notification")?;
+        }
+
+        if let Some(accrual) = changes.accrual {
+            let accounts = i64::try_from(accrual.accounts)
+                .map_err(|_| anyhow!("{} is an invalid account count", accrual.accounts))?;
+            self.dbtx
+                .execute(
+                    self.tables.sql("INSERT INTO {accrual} (as_of, rate, first_tx, accounts, interest_amount, completed) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (as_of) DO UPDATE SET accounts = EXCLUDED.accounts, interest_amount = EXCLUDED.interest_amount, completed = EXCLUDED.completed;").as_str(),
+                    &[
+                        &accrual.as_of,
+                        &accrual.rate.to_string(),
+                        &i64::from(accrual.tx_id),
+                        &accounts,
+                        &accrual.interest.minor_units(),
+                        &accrual.completed,
+                    ],
+                )
+                .context("failed saving accrual")?;
+        }
+
+        Ok(())
+    }
+
+    fn all_accounts(&mut self) -> Result<Vec<(ClientId, AccountRecord)>> {
+        let sql = self.tables.sql("SELECT id, available_amount, held_amount, total_amount, status, frozen, overdraft_limit, withdrawn_amount, withdrawal_day FROM {account} ORDER BY id;");
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading accounts")?
+            .iter()
+            .map(account_of)
+            .collect()
+    }
+
+    fn all_txs(&mut self) -> Result<Vec<(TxId, TxRecord)>> {
+        let sql = self.tables.sql(
+            "SELECT id, tx_type, client_id, amount, status, disputed_amount, ingested_at, timestamp FROM {tx} ORDER BY id;",
+        );
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading txs")?
+            .iter()
+            .map(txrecord_of)
+            .collect()
+    }
+
+    fn all_rejected(&mut self) -> Result<Vec<(Tx, TxOutcome)>> {
+        let sql = self.tables.sql(
+            "SELECT tx_id, tx_type, client_id, amount, to_client, timestamp, reason FROM {rejected_tx} ORDER BY seq;",
+        );
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading rejected txs")?
+            .iter()
+            .map(|row| Ok((tx_of(row)?, parse_of(row, 6)?)))
+            .collect()
+    }
+
+    fn all_events(&mut self) -> Result<Vec<Tx>> {
+        let sql = self.tables.sql(
+            "SELECT tx_id, tx_type, client_id, amount, to_client, timestamp FROM {event} ORDER BY seq;",
+        );
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading events")?
+            .iter()
+            .map(tx_of)
+            .collect()
+    }
+
+    fn all_audited(&mut self) -> Result<Vec<AuditEntry>> {
+        let sql = self.tables.sql(
+            "SELECT tx_id, tx_type, client_id, amount, to_client, timestamp, outcome, available_amount, held_amount, total_amount, seq FROM {audit_log} ORDER BY seq;",
+        );
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading audit log")?
+            .iter()
+            .map(|row| {
+                let seq: i64 = row.try_get(10)?;
+                Ok(AuditEntry {
+                    seq: u64::try_from(seq).map_err(|_| anyhow!("{} is an invalid seq", seq))?,
+                    tx: tx_of(row)?,
+                    outcome: parse_of(row, 6)?,
+                    available: Amount::from_minor_units(row.try_get(7)?),
+                    held: Amount::from_minor_units(row.try_get(8)?),
+                    total: Amount::from_minor_units(row.try_get(9)?),
+                })
+            })
+            .collect()
+    }
+
+    fn all_notifications(&mut self) -> Result<Vec<Notification>> {
+        let sql = self
+            .tables
+            .sql("SELECT seq, url, payload FROM {outbox} ORDER BY seq;");
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading the outbox")?
+            .iter()
+            .map(|row| {
+                let seq: i64 = row.try_get(0)?;
+                Ok(Notification {
+                    seq: u64::try_from(seq).map_err(|_| anyhow!("{} is an invalid seq", seq))?,
+                    url: row.try_get(1)?,
+                    payload: row.try_get(2)?,
+                })
+            })
+            .collect()
+    }
+
+    fn all_accruals(&mut self) -> Result<Vec<Accrual>> {
+        let sql = self.tables.sql(
+            "SELECT as_of, rate, first_tx, accounts, interest_amount, completed FROM {accrual} ORDER BY as_of;",
+        );
+        self.dbtx
+            .query(sql.as_str(), &[])
+            .context("failed reading accruals")?
+            .iter()
+            .map(|row| {
+                let accounts: i64 = row.try_get(3)?;
+                Ok(Accrual {
+                    as_of: row.try_get(0)?,
+                    rate: parse_of(row, 1)?,
+                    tx_id: tx_id_of(row, 2)?,
+                    accounts: u64::try_from(accounts)
+                        .map_err(|_| anyhow!("{} is an invalid account count", accounts))?,
+                    interest: Amount::from_minor_units(row.try_get(4)?),
+                    completed: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.dbtx
+            .batch_execute(&self.tables.sql("DELETE FROM {account}; DELETE FROM {tx};"))
+            .context("failed clearing balances")
+    }
+}
+
+impl Store for PostgresStore {
+    fn apply_deposit(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.apply_deposit(tx, config))
+    }
+
+    fn apply_withdrawal(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.apply_withdrawal(tx, config))
+    }
+
+    fn apply_transfer(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.apply_transfer(tx, config))
+    }
+
+    fn open_dispute(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.open_dispute(tx, config))
+    }
+
+    fn resolve(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.resolve(tx, config))
+    }
+
+    fn chargeback(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.chargeback(tx, config))
+    }
+
+    fn refund(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.refund(tx, config))
+    }
+
+    fn authorize(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.authorize(tx, config))
+    }
+
+    fn capture(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.capture(tx, config))
+    }
+
+    fn void(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.void(tx, config))
+    }
+
+    fn adjust(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.adjust(tx, config))
+    }
+
+    fn unlock(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.unlock(tx, config))
+    }
+
+    fn close_account(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.close_account(tx, config))
+    }
+
+    fn freeze(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.freeze(tx, config))
+    }
+
+    fn unfreeze(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.unfreeze(tx, config))
+    }
+
+    fn set_overdraft(&mut self, tx: &Tx, config: &Config) -> Result<TxOutcome> {
+        self.in_transaction(|records| records.set_overdraft(tx, config))
+    }
+
+    fn account(&mut self, client_id: ClientId) -> Result<Option<Account>> {
+        self.in_transaction(|records| records.account(client_id))
+    }
+
+    fn accounts(&mut self) -> Result<Vec<Account>> {
+        self.in_transaction(|records| records.accounts())
+    }
+
+    fn seed_accounts(&mut self, accounts: &[Account], config: &Config) -> Result<()> {
+        self.in_transaction(|records| records.seed_accounts(accounts, config))
+    }
+
+    fn reject(&mut self, tx: &Tx, reason: TxOutcome) -> Result<()> {
+        self.in_transaction(|records| records.reject(tx, reason))
+    }
+
+    fn rejected_withdrawals(&mut self) -> Result<Vec<(ClientId, TxId, Amount)>> {
+        self.in_transaction(|records| records.rejected_withdrawals())
+    }
+
+    fn rejected_txs(&mut self) -> Result<Vec<(Tx, TxOutcome)>> {
+        self.in_transaction(|records| records.rejected_txs())
+    }
+
+    fn applied_events(&mut self) -> Result<Vec<Tx>> {
+        self.in_transaction(|records| records.applied_events())
+    }
+
+    fn audit(&mut self, tx: &Tx, outcome: TxOutcome) -> Result<()> {
+        self.in_transaction(|records| records.audit(tx, outcome))
+    }
+
+    fn audit_log(&mut self, client_id: ClientId) -> Result<Vec<AuditEntry>> {
+        self.in_transaction(|records| records.audit_log(client_id))
+    }
+
+    fn stored_tx(&mut self, id: TxId) -> Result<Option<TxRecord>> {
+        self.in_transaction(|records| records.stored_tx(id))
+    }
+
+    fn stored_txs(&mut self, filter: &TxFilter) -> Result<Vec<(TxId, TxRecord)>> {
+        self.in_transaction(|records| records.stored_txs(filter))
+    }
+
+    fn checkpoint(&mut self, input_hash: &str) -> Result<Option<u64>> {
+        self.in_transaction(|records| records.checkpoint(input_hash))
+    }
+
+    fn save_checkpoint(&mut self, input_hash: &str, processed: u64) -> Result<()> {
+        self.in_transaction(|records| records.save_checkpoint(input_hash, processed))
+    }
+
+    fn accruals(&mut self) -> Result<Vec<Accrual>> {
+        self.in_transaction(|records| records.accruals())
+    }
+
+    fn save_accrual(&mut self, accrual: &Accrual) -> Result<()> {
+        self.in_transaction(|records| records.save_accrual(accrual))
+    }
+
+    fn pending_notifications(&mut self) -> Result<Vec<Notification>> {
+        self.in_transaction(|records| records.pending_notifications())
+    }
+
+    fn notification_sent(&mut self, seq: u64) -> Result<()> {
+        self.in_transaction(|records| records.notification_sent(seq))
+    }
+
+    fn clear_balances(&mut self) -> Result<()> {
+        self.in_transaction(|records| records.clear_balances())
+    }
+}
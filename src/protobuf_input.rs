@@ -0,0 +1,87 @@
+//! The protobuf reader, for length-delimited streams of the `Tx` message of
+//! `proto/tx.proto`, each message prefixed with its varint encoded length.
+
+use crate::{json_tx, Precision, ReadOptions, Tx, TxError};
+use anyhow::{anyhow, Context, Result};
+use prost::Message;
+use std::io::{ErrorKind, Read};
+
+/// The `Tx` message of `proto/tx.proto`
+#[derive(Clone, PartialEq, Message)]
+pub struct TxMessage {
+    #[prost(string, tag = "1")]
+    pub tx_type: String,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+    #[prost(uint32, optional, tag = "5")]
+    pub to: Option<u32>,
+    #[prost(int64, optional, tag = "6")]
+    pub timestamp: Option<i64>,
+}
+
+/// The transaction of a message, it goes through the json representation to share
+/// its amount handling
+pub(crate) fn message_tx(message: TxMessage, precision: Precision) -> Result<Tx> {
+    let element = serde_json::json!({
+        "type": message.tx_type,
+        "client": message.client,
+        "tx": message.tx,
+        "amount": message.amount,
+        "to": message.to,
+        "timestamp": message.timestamp,
+    });
+    json_tx(element, precision)
+}
+
+/// The length prefix of the next message, none at the end of the stream
+fn read_length(rdr: &mut impl Read) -> Result<Option<usize>> {
+    let mut length = 0u64;
+    for i in 0..10 {
+        let mut byte = [0u8];
+        match rdr.read_exact(&mut byte) {
+            Err(e) if i == 0 && e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result.context("failed reading message length")?,
+        }
+        length |= u64::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(length as usize));
+        }
+    }
+    Err(anyhow!("message length is longer than a varint"))
+}
+
+/// Reads the transactions of a length-delimited protobuf stream, a message at a
+/// time as the stream is pulled. A malformed message fails once it's reached and
+/// ends the stream, the messages after it can't be framed.
+pub fn read_protobuf_txs<R: Read>(
+    mut rdr: R,
+    options: &ReadOptions,
+) -> impl Iterator<Item = Result<Tx, TxError>> {
+    let precision = options.precision;
+    let mut messages = 0;
+    let mut failed = false;
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let mut next = || -> Result<Option<Tx>> {
+            let length = match read_length(&mut rdr)? {
+                Some(length) => length,
+                None => return Ok(None),
+            };
+            let mut buf = vec![0; length];
+            rdr.read_exact(&mut buf)
+                .context("failed reading message, the stream ended early")?;
+            let message = TxMessage::decode(buf.as_slice())?;
+            message_tx(message, precision).map(Some)
+        };
+        messages += 1;
+        let next = next().with_context(|| format!("invalid message {}", messages));
+        failed = next.is_err();
+        next.map_err(TxError::parse).transpose()
+    })
+}
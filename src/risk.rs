@@ -0,0 +1,239 @@
+//! The risk rules of `--risk-rules`, checks of how a client's transactions add up
+//! which run before each transaction is applied. A rule which trips rejects the
+//! transaction, flags it, or applies it and holds the account by freezing it until it's
+//! reviewed. The rules keep what they've seen in memory, their windows start with the
+//! run, and they're measured in the timestamps of the transactions, the time they're
+//! processed at for the ones without.
+
+use crate::{ClientId, Tx, TxType};
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::collections::{HashMap, VecDeque};
+
+/// A check of the transactions of a client, see [`RiskRules`]
+pub trait RiskRule: Send {
+    /// The name the rule is logged and counted by
+    fn name(&self) -> &str;
+    /// Whether `tx`, about to be applied at `now` in unix seconds, trips the rule
+    fn trips(&mut self, tx: &Tx, now: i64) -> bool;
+    /// Tracks `tx`, which was applied at `now` in unix seconds
+    fn applied(&mut self, tx: &Tx, now: i64);
+}
+
+/// What's done with a transaction tripping a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskAction {
+    /// isn't applied, recorded as `risk_rejected`
+    Reject,
+    /// is applied, and logged and counted as flagged by the rule
+    Flag,
+    /// is applied, then its account is frozen until it's reviewed and unfrozen
+    Hold,
+}
+
+/// Trips on a deposit of a client which already had `max_deposits` deposits in the
+/// last `window_secs`
+#[derive(Debug, Default, Deserialize)]
+pub struct DepositVelocity {
+    pub max_deposits: usize,
+    pub window_secs: i64,
+    #[serde(skip)]
+    deposits: HashMap<ClientId, VecDeque<i64>>,
+}
+
+impl DepositVelocity {
+    pub fn new(max_deposits: usize, window_secs: i64) -> Self {
+        DepositVelocity {
+            max_deposits,
+            window_secs,
+            ..DepositVelocity::default()
+        }
+    }
+}
+
+impl RiskRule for DepositVelocity {
+    fn name(&self) -> &str {
+        "deposit_velocity"
+    }
+
+    fn trips(&mut self, tx: &Tx, now: i64) -> bool {
+        if tx.tx_type != TxType::Deposit {
+            return false;
+        }
+        let window_secs = self.window_secs;
+        let deposits = self.deposits.entry(tx.client_id).or_default();
+        while deposits.front().is_some_and(|at| now - at >= window_secs) {
+            deposits.pop_front();
+        }
+        deposits.len() >= self.max_deposits
+    }
+
+    fn applied(&mut self, tx: &Tx, now: i64) {
+        if tx.tx_type == TxType::Deposit {
+            self.deposits
+                .entry(tx.client_id)
+                .or_default()
+                .push_back(now);
+        }
+    }
+}
+
+/// Trips on a withdrawal of a client less than `within_secs` after its last deposit
+#[derive(Debug, Default, Deserialize)]
+pub struct DepositThenWithdrawal {
+    pub within_secs: i64,
+    #[serde(skip)]
+    last_deposit: HashMap<ClientId, i64>,
+}
+
+impl DepositThenWithdrawal {
+    pub fn new(within_secs: i64) -> Self {
+        DepositThenWithdrawal {
+            within_secs,
+            ..DepositThenWithdrawal::default()
+        }
+    }
+}
+
+impl RiskRule for DepositThenWithdrawal {
+    fn name(&self) -> &str {
+        "deposit_then_withdrawal"
+    }
+
+    fn trips(&mut self, tx: &Tx, now: i64) -> bool {
+        tx.tx_type == TxType::Withdrawal
+            && self
+                .last_deposit
+                .get(&tx.client_id)
+                .is_some_and(|at| now - at < self.within_secs)
+    }
+
+    fn applied(&mut self, tx: &Tx, now: i64) {
+        if tx.tx_type == TxType::Deposit {
+            self.last_deposit.insert(tx.client_id, now);
+        }
+    }
+}
+
+/// Trips on a dispute taking the disputes of a client past `max_percent` of its
+/// deposits, once it made `min_deposits`
+#[derive(Debug, Default, Deserialize)]
+pub struct DisputeRate {
+    pub max_percent: u64,
+    pub min_deposits: u64,
+    /// the `(deposits, disputes)` of each client
+    #[serde(skip)]
+    counts: HashMap<ClientId, (u64, u64)>,
+}
+
+impl DisputeRate {
+    pub fn new(max_percent: u64, min_deposits: u64) -> Self {
+        DisputeRate {
+            max_percent,
+            min_deposits,
+            ..DisputeRate::default()
+        }
+    }
+}
+
+impl RiskRule for DisputeRate {
+    fn name(&self) -> &str {
+        "dispute_rate"
+    }
+
+    fn trips(&mut self, tx: &Tx, _: i64) -> bool {
+        if tx.tx_type != TxType::Dispute {
+            return false;
+        }
+        let (deposits, disputes) = self.counts.get(&tx.client_id).copied().unwrap_or_default();
+        deposits >= self.min_deposits
+            && (disputes + 1).saturating_mul(100) > deposits.saturating_mul(self.max_percent)
+    }
+
+    fn applied(&mut self, tx: &Tx, _: i64) {
+        let (deposits, disputes) = self.counts.entry(tx.client_id).or_default();
+        match tx.tx_type {
+            TxType::Deposit => *deposits += 1,
+            TxType::Dispute => *disputes += 1,
+            _ => {}
+        }
+    }
+}
+
+/// The rules checked before each transaction, with what's done with the ones they trip on
+#[derive(Default)]
+pub struct RiskRules {
+    rules: Vec<(Box<dyn RiskRule>, RiskAction)>,
+}
+
+/// A rule of the rules file, its action along with its settings
+#[derive(Deserialize)]
+struct Configured<R> {
+    action: RiskAction,
+    #[serde(flatten)]
+    rule: R,
+}
+
+/// The rules file, a table of settings for each of the shipped rules it turns on
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    deposit_velocity: Option<Configured<DepositVelocity>>,
+    deposit_then_withdrawal: Option<Configured<DepositThenWithdrawal>>,
+    dispute_rate: Option<Configured<DisputeRate>>,
+}
+
+impl RiskRules {
+    /// Adds `rule`, whose trips are handled by `action`
+    pub fn with_rule(mut self, rule: impl RiskRule + 'static, action: RiskAction) -> Self {
+        self.rules.push((Box::new(rule), action));
+        self
+    }
+
+    /// The rules of a toml rules file, e.g.
+    ///
+    /// ```toml
+    /// [deposit_velocity]
+    /// max_deposits = 5
+    /// window_secs = 3600
+    /// action = "reject"
+    /// ```
+    pub fn from_toml(rules: &str) -> Result<Self> {
+        let file: RulesFile = toml::from_str(rules).context("invalid risk rules")?;
+        let mut rules = RiskRules::default();
+        if let Some(configured) = file.deposit_velocity {
+            rules = rules.with_rule(configured.rule, configured.action);
+        }
+        if let Some(configured) = file.deposit_then_withdrawal {
+            rules = rules.with_rule(configured.rule, configured.action);
+        }
+        if let Some(configured) = file.dispute_rate {
+            rules = rules.with_rule(configured.rule, configured.action);
+        }
+        Ok(rules)
+    }
+
+    pub fn read(path: &str) -> Result<Self> {
+        let rules = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading risk rules {}", path))?;
+        RiskRules::from_toml(&rules).with_context(|| format!("in {}", path))
+    }
+
+    /// The names and actions of the rules `tx` trips
+    pub(crate) fn check(&mut self, tx: &Tx, now: i64) -> Vec<(String, RiskAction)> {
+        self.rules
+            .iter_mut()
+            .filter_map(|(rule, action)| {
+                rule.trips(tx, now)
+                    .then(|| (rule.name().to_string(), *action))
+            })
+            .collect()
+    }
+
+    pub(crate) fn applied(&mut self, tx: &Tx, now: i64) {
+        for (rule, _) in &mut self.rules {
+            rule.applied(tx, now);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! The rocksdb store, for transaction histories too large for sqlite. Each
+//! table of [`Tables`] is a column family, the accounts are keyed by client id
+//! and the transactions by tx id, and a transaction's changes are written as a
+//! single batch.
+
+use crate::{
+    kv_records::{
+        decode_account, decode_accrual, decode_audit, decode_notification, decode_rejected,
+        decode_tx, decode_txrecord, encode_account, encode_accrual, encode_audit,
+        encode_notification, encode_rejected, encode_tx, encode_txrecord,
+    },
+    AccountRecord, Accrual, AuditEntry, Changes, ClientId, Notification, Records, Tables, Tx, TxId,
+    TxOutcome, TxRecord,
+};
+use anyhow::{anyhow, Context, Result};
+use rocksdb::{ColumnFamily, IteratorMode, Options, WriteBatch, DB};
+use std::{convert::TryFrom, path::Path};
+
+pub struct RocksdbStore {
+    db: DB,
+    tables: Tables,
+    /// The key of the next rejected transaction, event, audit log entry or notification,
+    /// the logs and the outbox share it
+    next_seq: u64,
+}
+
+impl RocksdbStore {
+    /// Opens, or creates, the rocksdb database in the `path` directory
+    pub fn open(path: impl AsRef<Path>, tables: Tables) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let column_families = [
+            &tables.tx,
+            &tables.account,
+            &tables.rejected_tx,
+            &tables.event,
+            &tables.audit_log,
+            &tables.checkpoint,
+            &tables.outbox,
+            &tables.accrual,
+        ];
+        let db = DB::open_cf(&opts, path.as_ref(), column_families)
+            .with_context(|| format!("failed opening {}", path.as_ref().display()))?;
+
+        let mut store = RocksdbStore {
+            db,
+            tables,
+            next_seq: 0,
+        };
+        // the logs continue after the last key any of them has
+        let last_rejected = store.last_seq(&store.tables.rejected_tx)?;
+        let last_event = store.last_seq(&store.tables.event)?;
+        let last_audited = store.last_seq(&store.tables.audit_log)?;
+        let last_notification = store.last_seq(&store.tables.outbox)?;
+        store.next_seq = last_rejected
+            .max(last_event)
+            .max(last_audited)
+            .max(last_notification)
+            .map_or(0, |seq| seq + 1);
+
+        Ok(store)
+    }
+
+    fn cf(&self, name: &str) -> Result<&ColumnFamily> {
+        self.db
+            .cf_handle(name)
+            .ok_or_else(|| anyhow!("column family {} is missing", name))
+    }
+
+    fn last_seq(&self, name: &str) -> Result<Option<u64>> {
+        match self
+            .db
+            .iterator_cf(self.cf(name)?, IteratorMode::End)
+            .next()
+        {
+            Some(entry) => {
+                let (key, _) = entry.with_context(|| format!("failed reading {}", name))?;
+                let key = <[u8; 8]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid key of {}", key, name))?;
+                Ok(Some(u64::from_be_bytes(key)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the values of the `name` column family in key order
+    fn values<T>(&self, name: &str, decode: impl Fn(&[u8]) -> Result<T>) -> Result<Vec<T>> {
+        self.db
+            .iterator_cf(self.cf(name)?, IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry.with_context(|| format!("failed reading {}", name))?;
+                decode(&value)
+            })
+            .collect()
+    }
+}
+
+impl Records for RocksdbStore {
+    fn get_account(&mut self, client_id: ClientId) -> Result<Option<AccountRecord>> {
+        self.db
+            .get_cf(self.cf(&self.tables.account)?, client_id.to_be_bytes())
+            .context("failed reading account")?
+            .map(|value| decode_account(&value))
+            .transpose()
+    }
+
+    fn get_tx(&mut self, id: TxId) -> Result<Option<TxRecord>> {
+        self.db
+            .get_cf(self.cf(&self.tables.tx)?, id.to_be_bytes())
+            .context("failed reading tx")?
+            .map(|value| decode_txrecord(&value))
+            .transpose()
+    }
+
+    fn get_checkpoint(&mut self, input_hash: &str) -> Result<Option<u64>> {
+        self.db
+            .get_cf(self.cf(&self.tables.checkpoint)?, input_hash)
+            .context("failed reading checkpoint")?
+            .map(|value| {
+                let value = <[u8; 8]>::try_from(value.as_slice())
+                    .map_err(|_| anyhow!("{:?} is an invalid checkpoint", value))?;
+                Ok(u64::from_be_bytes(value))
+            })
+            .transpose()
+    }
+
+    fn commit(&mut self, changes: Changes) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        let mut seq = self.next_seq;
+
+        let accounts = self.cf(&self.tables.account)?;
+        for (client_id, account) in &changes.accounts {
+            batch.put_cf(accounts, client_id.to_be_bytes(), encode_account(account));
+        }
+        if let Some((id, txrecord)) = &changes.tx {
+            batch.put_cf(
+                self.cf(&self.tables.tx)?,
+                id.to_be_bytes(),
+                encode_txrecord(txrecord),
+            );
+        }
+        if let Some(rejected) = &changes.rejected {
+            let value = encode_rejected(rejected);
+            batch.put_cf(self.cf(&self.tables.rejected_tx)?, seq.to_be_bytes(), value);
+            seq += 1;
+        }
+        if let Some(tx) = &changes.event {
+            batch.put_cf(
+                self.cf(&self.tables.event)?,
+                seq.to_be_bytes(),
+                encode_tx(tx),
+            );
+            seq += 1;
+        }
+        if let Some(entry) = &changes.audit {
+            batch.put_cf(
+                self.cf(&self.tables.audit_log)?,
+                seq.to_be_bytes(),
+                encode_audit(entry),
+            );
+            seq += 1;
+        }
+        if let Some((input_hash, processed)) = &changes.checkpoint {
+            batch.put_cf(
+                self.cf(&self.tables.checkpoint)?,
+                input_hash,
+                processed.to_be_bytes(),
+            );
+        }
+        let outbox = self.cf(&self.tables.outbox)?;
+        for notification in &changes.notifications {
+            batch.put_cf(outbox, seq.to_be_bytes(), encode_notification(notification));
+            seq += 1;
+        }
+        if let Some(notified) = changes.notified {
+            batch.delete_cf(outbox, notified.to_be_bytes());
+        }
+        if let Some(accrual) = &changes.accrual {
+            batch.put_cf(
+                self.cf(&self.tables.accrual)?,
+                &accrual.as_of,
+                encode_accrual(accrual),
+            );
+        }
+
+        self.db.write(batch).context("failed committing changes")?;
+        self.next_seq = seq;
+        Ok(())
+    }
+
+    fn all_accounts(&mut self) -> Result<Vec<(ClientId, AccountRecord)>> {
+        self.db
+            .iterator_cf(self.cf(&self.tables.account)?, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading accounts")?;
+                let key = <[u8; 2]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid client id", key))?;
+                Ok((ClientId::from_be_bytes(key), decode_account(&value)?))
+            })
+            .collect()
+    }
+
+    fn all_txs(&mut self) -> Result<Vec<(TxId, TxRecord)>> {
+        self.db
+            .iterator_cf(self.cf(&self.tables.tx)?, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading txs")?;
+                let key = <[u8; 4]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid tx id", key))?;
+                Ok((TxId::from_be_bytes(key), decode_txrecord(&value)?))
+            })
+            .collect()
+    }
+
+    fn all_rejected(&mut self) -> Result<Vec<(Tx, TxOutcome)>> {
+        self.values(&self.tables.rejected_tx, decode_rejected)
+    }
+
+    fn all_events(&mut self) -> Result<Vec<Tx>> {
+        self.values(&self.tables.event, decode_tx)
+    }
+
+    /// The key an entry is stored under is its seq
+    fn all_audited(&mut self) -> Result<Vec<AuditEntry>> {
+        let name = &self.tables.audit_log;
+        self.db
+            .iterator_cf(self.cf(name)?, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.with_context(|| format!("failed reading {}", name))?;
+                let key = <[u8; 8]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid key of {}", key, name))?;
+                decode_audit(u64::from_be_bytes(key), &value)
+            })
+            .collect()
+    }
+
+    /// The key a notification is stored under is its seq
+    fn all_notifications(&mut self) -> Result<Vec<Notification>> {
+        let name = &self.tables.outbox;
+        self.db
+            .iterator_cf(self.cf(name)?, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.with_context(|| format!("failed reading {}", name))?;
+                let key = <[u8; 8]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid key of {}", key, name))?;
+                decode_notification(u64::from_be_bytes(key), &value)
+            })
+            .collect()
+    }
+
+    /// The period an accrual is stored under orders them
+    fn all_accruals(&mut self) -> Result<Vec<Accrual>> {
+        let name = &self.tables.accrual;
+        self.db
+            .iterator_cf(self.cf(name)?, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.with_context(|| format!("failed reading {}", name))?;
+                decode_accrual(&key, &value)
+            })
+            .collect()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        // a key one byte longer than any stored one bounds the whole range
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(self.cf(&self.tables.account)?, vec![0; 2], vec![0xff; 3]);
+        batch.delete_range_cf(self.cf(&self.tables.tx)?, vec![0; 4], vec![0xff; 5]);
+        self.db.write(batch).context("failed clearing balances")
+    }
+}
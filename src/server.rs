@@ -0,0 +1,304 @@
+//! The http api of `serve`, for services submitting transactions and reading the
+//! accounts back without writing files, and its `/stream` websocket for the tools
+//! pushing them interactively. The requests are received on threads of their own
+//! while the engine stays on the calling thread, taking them one at a time over a
+//! channel, the stores taking a single writer.
+
+use crate::{check_headers, json_tx, Account, ClientId, Engine, ReadOptions, Store, TxError, TxId};
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+/// How often the server checks whether it should stop while no request comes in
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A request for the engine, run on its thread
+type Call<S> = Box<dyn FnOnce(&mut Engine<S>) + Send>;
+
+/// Serves the api on `addr` until `should_stop` says otherwise, e.g. on SIGINT. A
+/// websocket still open is then answered with an error on its next message.
+pub fn serve<S: Store + 'static>(
+    engine: &mut Engine<S>,
+    addr: SocketAddr,
+    options: &ReadOptions,
+    should_stop: impl Fn() -> bool,
+) -> Result<()> {
+    let server = Arc::new(
+        Server::http(addr).map_err(|e| anyhow!("failed serving the api on {}: {}", addr, e))?,
+    );
+    let (calls, engine_calls) = mpsc::channel();
+    let receiving = {
+        let server = server.clone();
+        let options = options.clone();
+        thread::Builder::new()
+            .name("http".to_string())
+            .spawn(move || receive(&server, &calls, &options))
+            .context("failed spawning the http server")?
+    };
+    tracing::info!(%addr, "serving the api");
+
+    while !should_stop() {
+        match engine_calls.recv_timeout(POLL_INTERVAL) {
+            Ok(call) => call(engine),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    server.unblock();
+    drop(engine_calls);
+    receiving
+        .join()
+        .unwrap_or_else(|panicked| std::panic::resume_unwind(panicked));
+    Ok(())
+}
+
+/// Hands the requests over to the engine as they come, each websocket to a thread
+/// of its own
+fn receive<S: Store + 'static>(
+    server: &Server,
+    calls: &mpsc::Sender<Call<S>>,
+    options: &ReadOptions,
+) {
+    for mut request in server.incoming_requests() {
+        if request.url() == "/stream" {
+            let (calls, options) = (calls.clone(), options.clone());
+            let streaming = thread::Builder::new()
+                .name("websocket".to_string())
+                .spawn(move || stream(request, &calls, &options));
+            if let Err(e) = streaming {
+                tracing::warn!(error = %e, "failed spawning a websocket");
+            }
+            continue;
+        }
+
+        let mut body = String::new();
+        let read = request.as_reader().read_to_string(&mut body);
+        let options = options.clone();
+        let call: Call<S> = Box::new(move |engine| {
+            let answer = match read {
+                Ok(_) => route(engine, &options, request.method(), request.url(), &body)
+                    .unwrap_or_else(|e| (500, json!({ "error": format!("{:#}", e) }))),
+                Err(e) => (
+                    400,
+                    json!({ "error": format!("failed reading the body, {}", e) }),
+                ),
+            };
+            respond(request, answer)
+        });
+        // the engine stopped
+        if calls.send(call).is_err() {
+            return;
+        }
+    }
+}
+
+fn respond(request: Request, (status, body): (u16, Value)) {
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(
+            "Content-Type: application/json"
+                .parse::<Header>()
+                .expect("a valid header"),
+        );
+    if let Err(e) = request.respond(response) {
+        tracing::warn!(error = %e, "failed responding");
+    }
+}
+
+/// Answers each text message of the websocket of `request` as `POST /transactions`
+/// answers its body, with the outcome of its transaction, or of each of an array of
+/// them. A failing store answers with its error and closes the socket.
+fn stream<S: Store + 'static>(
+    request: Request,
+    calls: &mpsc::Sender<Call<S>>,
+    options: &ReadOptions,
+) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| derive_accept_key(h.value.as_str().as_bytes()));
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let expected = json!({ "error": "/stream expects a websocket" });
+            return respond(request, (400, expected));
+        }
+    };
+    let accept = format!("Sec-WebSocket-Accept: {}", key)
+        .parse::<Header>()
+        .expect("a valid header");
+    let upgraded = request.upgrade("websocket", Response::empty(101).with_header(accept));
+    let mut socket = WebSocket::from_raw_socket(upgraded, Role::Server, None);
+
+    loop {
+        let text = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Binary(_)) => {
+                let answer = json!({ "error": "expected a text message" });
+                if socket.send(Message::text(answer.to_string())).is_err() {
+                    return;
+                }
+                continue;
+            }
+            // pings are answered as they're read
+            Ok(_) => continue,
+            // closed, the close frame is answered as it's read
+            Err(_) => return,
+        };
+
+        let (reply, replied) = mpsc::sync_channel(1);
+        let options = options.clone();
+        let call: Call<S> = Box::new(move |engine| {
+            let _ = reply.send(submit(engine, &options, &text));
+        });
+        let (answer, failed) = match calls.send(call).ok().and_then(|_| replied.recv().ok()) {
+            Some(Ok((_, answer))) => (answer, false),
+            Some(Err(e)) => (json!({ "error": format!("{:#}", e) }), true),
+            None => (json!({ "error": "the server is stopping" }), true),
+        };
+        if socket.send(Message::text(answer.to_string())).is_err() || failed {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+            return;
+        }
+    }
+}
+
+/// The status and body of the response to a request. A failing store fails it as
+/// a whole, the transactions of a batch applied before it are kept.
+pub(crate) fn route<S: Store>(
+    engine: &mut Engine<S>,
+    options: &ReadOptions,
+    method: &Method,
+    url: &str,
+    body: &str,
+) -> Result<(u16, Value)> {
+    let path: Vec<&str> = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match (method, path.as_slice()) {
+        (Method::Post, ["transactions"]) => submit(engine, options, body),
+        (Method::Get, ["accounts"]) => {
+            let accounts = engine.accounts()?.iter().map(account_json).collect();
+            Ok((200, Value::Array(accounts)))
+        }
+        (Method::Get, ["accounts", client]) => match client.parse::<ClientId>() {
+            Ok(client_id) => Ok(match engine.account(client_id)? {
+                Some(account) => (200, account_json(&account)),
+                None => not_found(format!("no account of client {}", client_id)),
+            }),
+            Err(_) => Ok(bad_request(format!("{} is an invalid client", client))),
+        },
+        (Method::Get, ["transactions", id]) => match id.parse::<TxId>() {
+            Ok(id) => Ok(match engine.transaction(id)? {
+                Some(tx) => (
+                    200,
+                    json!({
+                        "tx": id,
+                        "type": tx.tx_type.to_string(),
+                        "client": tx.client_id,
+                        "amount": tx.amount,
+                        "status": tx.status.to_string(),
+                        "disputed": tx.disputed,
+                        "timestamp": tx.happened(),
+                    }),
+                ),
+                None => not_found(format!("no transaction {}", id)),
+            }),
+            Err(_) => Ok(bad_request(format!("{} is an invalid transaction", id))),
+        },
+        (_, ["transactions"])
+        | (_, ["accounts"])
+        | (_, ["accounts", _])
+        | (_, ["transactions", _]) => Ok((
+            405,
+            json!({ "error": format!("{} isn't allowed on {}", method, url) }),
+        )),
+        _ => Ok(not_found(format!("no route {}", url))),
+    }
+}
+
+/// Processes a transaction, or an array of them, given as json objects with the csv
+/// columns as fields, answering with the outcome of each
+fn submit<S: Store>(
+    engine: &mut Engine<S>,
+    options: &ReadOptions,
+    body: &str,
+) -> Result<(u16, Value)> {
+    let elements = match serde_json::from_str(body) {
+        Ok(Value::Array(elements)) => elements,
+        Ok(element @ Value::Object(_)) => {
+            let (status, outcome) = submit_one(engine, options, element)?;
+            return Ok((status, outcome));
+        }
+        Ok(_) => return Ok(bad_request("expected a transaction or an array of them")),
+        Err(e) => return Ok(bad_request(format!("invalid json, {}", e))),
+    };
+
+    let mut outcomes = Vec::with_capacity(elements.len());
+    for element in elements {
+        outcomes.push(submit_one(engine, options, element)?.1);
+    }
+    Ok((200, Value::Array(outcomes)))
+}
+
+fn submit_one<S: Store>(
+    engine: &mut Engine<S>,
+    options: &ReadOptions,
+    element: Value,
+) -> Result<(u16, Value)> {
+    let parsed = match element.as_object() {
+        Some(fields) => check_headers(fields.keys().map(String::as_str), options),
+        None => Err(anyhow!("expected a transaction object")),
+    }
+    .and_then(|_| json_tx(element, options.precision));
+    let tx = match parsed {
+        Ok(tx) => tx,
+        Err(e) => return Ok(bad_request(format!("{:#}", e))),
+    };
+
+    let id = tx.id;
+    match engine.submit(tx) {
+        Ok(outcome) => Ok((200, json!({ "tx": id, "outcome": outcome.to_string() }))),
+        Err(e @ TxError::Storage(_)) => Err(e.into()),
+        Err(e) => Ok((
+            422,
+            json!({ "tx": id, "error": format!("{:#}", anyhow::Error::from(e)) }),
+        )),
+    }
+}
+
+/// An account with the columns of the report
+fn account_json(account: &Account) -> Value {
+    json!({
+        "client": account.client_id,
+        "available": account.available,
+        "held": account.held,
+        "total": account.total,
+        "locked": account.locked,
+        "frozen": account.frozen,
+    })
+}
+
+fn bad_request(message: impl Into<String>) -> (u16, Value) {
+    (400, json!({ "error": message.into() }))
+}
+
+fn not_found(message: impl Into<String>) -> (u16, Value) {
+    (404, json!({ "error": message.into() }))
+}
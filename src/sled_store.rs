@@ -0,0 +1,269 @@
+//! The sled store, an embedded key-value database for single binary deployments.
+//! The accounts are keyed by client id and the transactions by tx id, each
+//! table of [`Tables`] is a tree and a transaction's changes are committed
+//! across them atomically.
+
+use crate::{
+    kv_records::{
+        decode_account, decode_accrual, decode_audit, decode_notification, decode_rejected,
+        decode_tx, decode_txrecord, encode_account, encode_accrual, encode_audit,
+        encode_notification, encode_rejected, encode_tx, encode_txrecord,
+    },
+    AccountRecord, Accrual, AuditEntry, Changes, ClientId, Notification, Records, Tables, Tx, TxId,
+    TxOutcome, TxRecord,
+};
+use anyhow::{anyhow, Context, Result};
+use sled::{
+    transaction::{ConflictableTransactionResult, TransactionError},
+    Db, IVec, Transactional, Tree,
+};
+use std::{convert::TryFrom, path::Path};
+
+/// The writes are flushed to disk when the store is dropped, sled only does so
+/// periodically on its own
+pub struct SledStore {
+    db: Db,
+    accounts: Tree,
+    txs: Tree,
+    rejected: Tree,
+    events: Tree,
+    audited: Tree,
+    checkpoints: Tree,
+    outbox: Tree,
+    accruals: Tree,
+}
+
+impl SledStore {
+    /// Opens, or creates, the sled database in the `path` directory
+    pub fn open(path: impl AsRef<Path>, tables: Tables) -> Result<Self> {
+        let db = sled::open(path.as_ref())
+            .with_context(|| format!("failed opening {}", path.as_ref().display()))?;
+        let tree = |name: &str| {
+            db.open_tree(name)
+                .with_context(|| format!("failed opening tree {}", name))
+        };
+
+        Ok(SledStore {
+            accounts: tree(&tables.account)?,
+            txs: tree(&tables.tx)?,
+            rejected: tree(&tables.rejected_tx)?,
+            events: tree(&tables.event)?,
+            audited: tree(&tables.audit_log)?,
+            checkpoints: tree(&tables.checkpoint)?,
+            outbox: tree(&tables.outbox)?,
+            accruals: tree(&tables.accrual)?,
+            db,
+        })
+    }
+}
+
+impl Drop for SledStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.db.flush() {
+            tracing::error!(error = %e, "failed flushing sled database");
+        }
+    }
+}
+
+/// Reads the values of `tree` in key order
+fn values<T>(tree: &Tree, decode: impl Fn(&IVec) -> Result<T>) -> Result<Vec<T>> {
+    tree.iter()
+        .values()
+        .map(|value| decode(&value.context("failed reading tree")?))
+        .collect()
+}
+
+impl Records for SledStore {
+    fn get_account(&mut self, client_id: ClientId) -> Result<Option<AccountRecord>> {
+        self.accounts
+            .get(client_id.to_be_bytes())
+            .context("failed reading account")?
+            .map(|value| decode_account(&value))
+            .transpose()
+    }
+
+    fn get_tx(&mut self, id: TxId) -> Result<Option<TxRecord>> {
+        self.txs
+            .get(id.to_be_bytes())
+            .context("failed reading tx")?
+            .map(|value| decode_txrecord(&value))
+            .transpose()
+    }
+
+    fn get_checkpoint(&mut self, input_hash: &str) -> Result<Option<u64>> {
+        self.checkpoints
+            .get(input_hash)
+            .context("failed reading checkpoint")?
+            .map(|value| {
+                let value = <[u8; 8]>::try_from(value.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid checkpoint", value))?;
+                Ok(u64::from_be_bytes(value))
+            })
+            .transpose()
+    }
+
+    fn commit(&mut self, changes: Changes) -> Result<()> {
+        let accounts: Vec<_> = changes
+            .accounts
+            .iter()
+            .map(|(client_id, account)| (client_id.to_be_bytes(), encode_account(account)))
+            .collect();
+        let tx = changes
+            .tx
+            .as_ref()
+            .map(|(id, txrecord)| (id.to_be_bytes(), encode_txrecord(txrecord)));
+        let rejected = changes.rejected.as_ref().map(encode_rejected);
+        let event = changes.event.as_ref().map(encode_tx);
+        let audit = changes.audit.as_ref().map(encode_audit);
+        let checkpoint = changes.checkpoint.as_ref();
+        let notifications: Vec<_> = changes
+            .notifications
+            .iter()
+            .map(encode_notification)
+            .collect();
+        let notified = changes.notified.map(u64::to_be_bytes);
+        let accrual = changes
+            .accrual
+            .as_ref()
+            .map(|accrual| (accrual.as_of.as_bytes(), encode_accrual(accrual)));
+
+        // the rejected, event and audit logs and the outbox are keyed by a generated
+        // id, kept in order by encoding it big endian
+        let trees = (
+            &self.accounts,
+            &self.txs,
+            &self.rejected,
+            &self.events,
+            &self.audited,
+            &self.checkpoints,
+            &self.outbox,
+            &self.accruals,
+        );
+        trees
+            .transaction(
+                |(
+                    accounts_tree,
+                    txs_tree,
+                    rejected_tree,
+                    events_tree,
+                    audited_tree,
+                    checkpoints_tree,
+                    outbox_tree,
+                    accruals_tree,
+                )|
+                 -> ConflictableTransactionResult<()> {
+                    for (key, value) in &accounts {
+                        accounts_tree.insert(key, value.as_bytes())?;
+                    }
+                    if let Some((key, value)) = &tx {
+                        txs_tree.insert(key, value.as_bytes())?;
+                    }
+                    if let Some(value) = &rejected {
+                        let key = rejected_tree.generate_id()?.to_be_bytes();
+                        rejected_tree.insert(&key, value.as_bytes())?;
+                    }
+                    if let Some(value) = &event {
+                        let key = events_tree.generate_id()?.to_be_bytes();
+                        events_tree.insert(&key, value.as_bytes())?;
+                    }
+                    if let Some(value) = &audit {
+                        let key = audited_tree.generate_id()?.to_be_bytes();
+                        audited_tree.insert(&key, value.as_bytes())?;
+                    }
+                    if let Some((input_hash, processed)) = checkpoint {
+                        checkpoints_tree.insert(input_hash.as_bytes(), &processed.to_be_bytes())?;
+                    }
+                    for value in &notifications {
+                        let key = outbox_tree.generate_id()?.to_be_bytes();
+                        outbox_tree.insert(&key, value.as_bytes())?;
+                    }
+                    if let Some(key) = &notified {
+                        outbox_tree.remove(key)?;
+                    }
+                    if let Some((as_of, value)) = &accrual {
+                        accruals_tree.insert(*as_of, value.as_bytes())?;
+                    }
+                    Ok(())
+                },
+            )
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => anyhow::Error::from(e),
+                TransactionError::Abort(()) => anyhow!("transaction aborted"),
+            })
+            .context("failed committing changes")
+    }
+
+    fn all_accounts(&mut self) -> Result<Vec<(ClientId, AccountRecord)>> {
+        self.accounts
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading accounts")?;
+                let key = <[u8; 2]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid client id", key))?;
+                Ok((ClientId::from_be_bytes(key), decode_account(&value)?))
+            })
+            .collect()
+    }
+
+    fn all_txs(&mut self) -> Result<Vec<(TxId, TxRecord)>> {
+        self.txs
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading txs")?;
+                let key = <[u8; 4]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid tx id", key))?;
+                Ok((TxId::from_be_bytes(key), decode_txrecord(&value)?))
+            })
+            .collect()
+    }
+
+    fn all_rejected(&mut self) -> Result<Vec<(Tx, TxOutcome)>> {
+        values(&self.rejected, |value| decode_rejected(value))
+    }
+
+    fn all_events(&mut self) -> Result<Vec<Tx>> {
+        values(&self.events, |value| decode_tx(value))
+    }
+
+    /// The generated id an entry is keyed by is its seq
+    fn all_audited(&mut self) -> Result<Vec<AuditEntry>> {
+        self.audited
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading audit log")?;
+                let key = <[u8; 8]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid seq", key))?;
+                decode_audit(u64::from_be_bytes(key), &value)
+            })
+            .collect()
+    }
+
+    /// The generated id a notification is keyed by is its seq
+    fn all_notifications(&mut self) -> Result<Vec<Notification>> {
+        self.outbox
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading outbox")?;
+                let key = <[u8; 8]>::try_from(key.as_ref())
+                    .map_err(|_| anyhow!("{:?} is an invalid seq", key))?;
+                decode_notification(u64::from_be_bytes(key), &value)
+            })
+            .collect()
+    }
+
+    /// The period an accrual is keyed by orders them
+    fn all_accruals(&mut self) -> Result<Vec<Accrual>> {
+        self.accruals
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.context("failed reading accruals")?;
+                decode_accrual(&key, &value)
+            })
+            .collect()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.accounts.clear().context("failed clearing accounts")?;
+        self.txs.clear().context("failed clearing txs")
+    }
+}
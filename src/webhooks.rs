@@ -0,0 +1,128 @@
+//! The delivery of the webhook notifications of `--webhook`, for the fraud teams
+//! watching the chargebacks and the accounts they lock. The store queues them in its
+//! outbox along with the chargeback, and they're posted on a thread of their own so a
+//! slow endpoint doesn't hold the transactions up. A notification is only dropped from
+//! the outbox once its endpoint answered with a success, one given up on is posted
+//! again by the next run, so an endpoint may see one twice but never misses one.
+
+use crate::Notification;
+use anyhow::{Context, Result};
+use std::{
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How the notifications are posted
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    /// how many times a failed post is retried before it's left for the next run
+    pub retries: u32,
+    /// the wait before the first retry, doubled before each one after it
+    pub backoff: Duration,
+    /// how long a post waits for its endpoint to answer
+    pub timeout: Duration,
+}
+
+impl Default for WebhookOptions {
+    fn default() -> Self {
+        WebhookOptions {
+            retries: 5,
+            backoff: Duration::from_millis(500),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Posts the notifications it's handed over, in order, reporting back the seqs of the
+/// delivered ones
+pub(crate) struct Notifier {
+    notifications: mpsc::Sender<Notification>,
+    delivered: mpsc::Receiver<u64>,
+    /// the seq of the last notification handed over, the outbox keeps the ones not
+    /// delivered yet
+    handed: Option<u64>,
+    delivering: JoinHandle<()>,
+}
+
+impl Notifier {
+    pub(crate) fn spawn(options: &WebhookOptions) -> Result<Self> {
+        let (notifications, undelivered) = mpsc::channel::<Notification>();
+        let (sent, delivered) = mpsc::channel();
+        let options = options.clone();
+        let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+        let delivering = thread::Builder::new()
+            .name("webhooks".to_string())
+            .spawn(move || {
+                for notification in undelivered {
+                    if post(&agent, &notification, &options) {
+                        // the engine is gone, what's left stays in the outbox
+                        if sent.send(notification.seq).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .context("failed spawning the webhook delivery")?;
+        Ok(Notifier {
+            notifications,
+            delivered,
+            handed: None,
+            delivering,
+        })
+    }
+
+    /// Hands over the notifications of `pending` it wasn't handed yet
+    pub(crate) fn hand_over(&mut self, pending: Vec<Notification>) {
+        for notification in pending {
+            if self.handed.is_none_or(|handed| notification.seq > handed) {
+                self.handed = Some(notification.seq);
+                // the delivery only ends once the notifier is finished
+                let _ = self.notifications.send(notification);
+            }
+        }
+    }
+
+    /// The seqs delivered since last asked
+    pub(crate) fn delivered(&self) -> Vec<u64> {
+        self.delivered.try_iter().collect()
+    }
+
+    /// Waits for the notifications handed over to be posted, or given up on, returning
+    /// the seqs delivered since last asked
+    pub(crate) fn finish(self) -> Vec<u64> {
+        drop(self.notifications);
+        self.delivering
+            .join()
+            .unwrap_or_else(|panicked| std::panic::resume_unwind(panicked));
+        self.delivered.try_iter().collect()
+    }
+}
+
+/// Posts `notification`, retrying with an exponential backoff, and returns whether its
+/// endpoint took it
+fn post(agent: &ureq::Agent, notification: &Notification, options: &WebhookOptions) -> bool {
+    let mut backoff = options.backoff;
+    for attempt in 0..=options.retries {
+        if attempt > 0 {
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+        let posted = agent
+            .post(&notification.url)
+            .set("Content-Type", "application/json")
+            .send_string(&notification.payload);
+        match posted {
+            Ok(_) => return true,
+            Err(e) => {
+                tracing::warn!(url = %notification.url, seq = notification.seq, attempt, error = %e, "failed posting a notification")
+            }
+        }
+    }
+    tracing::error!(
+        url = %notification.url,
+        seq = notification.seq,
+        "gave up posting a notification, it's left in the outbox for the next run"
+    );
+    false
+}